@@ -0,0 +1,80 @@
+//! Accepts message streams pushed by subscribers
+//!
+//! See `BackgroundThreadHandle::into_push_client` in the subscriber crate.
+use crate::storage::*;
+
+use futures::{Future, Stream};
+
+use tokio::net::TcpListener;
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tower_grpc::{Request, Response, Streaming};
+use tower_hyper::server::{Http, Server};
+
+/// Receives the messages of a single pushing process, see `accept_with`
+pub type Sink = Arc<dyn Fn(ListenResponse) + Send + Sync>;
+
+#[derive(Clone)]
+struct Collector {
+    sink: Sink,
+}
+
+impl messages::server::ConsoleCollector for Collector {
+    type PushFuture =
+        Box<dyn Future<Item = Response<PushResponse>, Error = tower_grpc::Status> + Send>;
+
+    fn push(&mut self, request: Request<Streaming<ListenResponse>>) -> Self::PushFuture {
+        let sink = self.sink.clone();
+        let push = request
+            .into_inner()
+            .for_each(move |response| {
+                sink(response);
+                Ok(())
+            })
+            .map(|()| Response::new(PushResponse {}));
+        Box::new(push)
+    }
+}
+
+/// Accepts pushing subscribers on `addr`, e.g. `[::]:50052`
+/// Internally locks and updates the `Store`, each process is added as a source `push://<peer>`
+///
+/// Blocks until the listener fails
+pub fn accept(store: StoreHandle, addr: &str) {
+    accept_with(addr, move |peer| {
+        let store = store.clone();
+        let source = store.add_source(&format!("push://{}", peer));
+        Arc::new(move |response: ListenResponse| store.handle_response(source, response))
+    })
+}
+
+/// Like `accept`, but creates a `Sink` per connected process, e.g. to relay messages
+///
+/// `new_sink` is called with the address of the process.
+pub fn accept_with<F>(addr: &str, mut new_sink: F)
+where
+    F: FnMut(SocketAddr) -> Sink + Send + 'static,
+{
+    let bind = TcpListener::bind(&addr.parse().expect("Invalid address")).expect("bind");
+    let http = Http::new().http2_only(true).clone();
+
+    let serve = bind
+        .incoming()
+        .for_each(move |sock| {
+            sock.set_nodelay(true)?;
+            let collector = Collector {
+                sink: new_sink(sock.peer_addr()?),
+            };
+            let mut server = Server::new(messages::server::ConsoleCollectorServer::new(collector));
+            let serve = server.serve_with(sock, http.clone());
+            tokio::spawn(serve.map_err(|_| {
+                // Ignore connection reset
+            }));
+            Ok(())
+        })
+        .map_err(|e| eprintln!("accept error: {}", e));
+
+    tokio::run(serve);
+}
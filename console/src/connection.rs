@@ -4,25 +4,41 @@ use futures::{Future, Stream};
 
 use hyper::client::connect::{Destination, HttpConnector};
 
+use tokio::runtime::Runtime;
+
 use tower_grpc::Request;
 use tower_hyper::{client, util};
 use tower_util::MakeService;
 
 /// Connects to the remote endpoint
-/// Internally locks and updates the `Store`
+/// Internally locks and updates the `Store`, the endpoint is added as a source named `addr`
 ///
 /// Blocks until the connection is reset by the endpoint
 pub fn listen(store: StoreHandle, addr: &str) {
-    let uri: http::Uri = addr.parse().unwrap();
+    let source = store.add_source(addr);
+    listen_with(addr, move |response| {
+        store.handle_response(source, response)
+    })
+    .unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// Like `listen`, but hands each `ListenResponse` to `f`, e.g. to relay it
+///
+/// Returns an error, if the endpoint couldn't be reached.
+pub fn listen_with<F>(addr: &str, mut f: F) -> Result<(), failure::Error>
+where
+    F: FnMut(ListenResponse) + Send + 'static,
+{
+    let uri: http::Uri = addr.parse()?;
 
-    let dst = Destination::try_from_uri(uri.clone()).unwrap();
+    let dst = Destination::try_from_uri(uri.clone())?;
     let connector = util::Connector::new(HttpConnector::new(4));
     let settings = client::Builder::new().http2_only(true).clone();
     let mut make_client = client::Connect::with_builder(connector, settings);
 
     let fetch_events = make_client
         .make_service(dst)
-        .map_err(|e| panic!("connect error: {:?}", e))
+        .map_err(|e| failure::format_err!("connect error: {:?}", e))
         .and_then(move |conn| {
             use messages::client::ConsoleForwarder;
 
@@ -32,18 +48,30 @@ pub fn listen(store: StoreHandle, addr: &str) {
                 .unwrap();
 
             // Wait until the client is ready...
-            ConsoleForwarder::new(conn).ready()
+            ConsoleForwarder::new(conn)
+                .ready()
+                .map_err(|e| failure::format_err!("{:?}", e))
         })
-        .and_then(|mut client| client.listen(Request::new(ListenRequest {})))
-        .and_then(move |stream_response| {
-            stream_response.into_inner().for_each(move |response| {
-                store.handle(response.variant.expect("No variant on response"));
-                Ok(())
-            })
+        .and_then(|mut client| {
+            // `DebugRecord::pretty` isn't displayed yet
+            client
+                .listen(Request::new(ListenRequest {
+                    pretty_debug: false,
+                }))
+                .map_err(|e| failure::format_err!("{:?}", e))
         })
-        .map_err(|_| {
-            // TODO: Errors like connection reset are ignored for now
+        .and_then(move |stream_response| {
+            stream_response
+                .into_inner()
+                .for_each(move |response| {
+                    f(response);
+                    Ok(())
+                })
+                .or_else(|_| {
+                    // TODO: Errors like connection reset are ignored for now
+                    Ok(())
+                })
         });
 
-    tokio::run(fetch_events);
+    Runtime::new()?.block_on(fetch_events)
 }
@@ -0,0 +1,148 @@
+//! Discovery of local processes, which announced their `ConsoleForwarder` address
+//!
+//! See `BackgroundThreadHandle::run_discoverable` in the subscriber crate.
+use crate::storage::*;
+
+use prost::Message;
+
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// `$XDG_RUNTIME_DIR/tracing-console`, falls back to a per-user temporary directory
+///
+/// Must match the directory of the subscriber crate.
+fn directory() -> PathBuf {
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("tracing-console"),
+        None => fallback_directory(),
+    }
+}
+
+#[cfg(unix)]
+fn fallback_directory() -> PathBuf {
+    // Safe: `getuid` has no preconditions and always succeeds
+    let uid = unsafe { libc::getuid() };
+    env::temp_dir().join(format!("tracing-console-{}", uid))
+}
+
+#[cfg(not(unix))]
+fn fallback_directory() -> PathBuf {
+    // The temporary directory is per user already
+    env::temp_dir().join("tracing-console")
+}
+
+/// Whether only the current user can access `dir`
+#[cfg(unix)]
+pub(crate) fn is_private(dir: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    // Safe: `getuid` has no preconditions and always succeeds
+    let uid = unsafe { libc::getuid() };
+    match fs::symlink_metadata(dir) {
+        Ok(metadata) => metadata.is_dir() && metadata.uid() == uid && metadata.mode() & 0o077 == 0,
+        Err(_) => false,
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn is_private(_dir: &Path) -> bool {
+    true
+}
+
+/// Lists all live, discoverable processes, ordered by pid
+///
+/// Files left behind by processes which are gone, are removed.
+/// A directory other users can access is ignored, they could plant announcements.
+pub fn discover() -> Vec<ProcessInfo> {
+    let dir = directory();
+    if !is_private(&dir) {
+        return vec![];
+    }
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut processes: Vec<ProcessInfo> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        // Skip files which are currently written
+        .filter(|path| !is_hidden(path))
+        .filter_map(|path| {
+            let info = ProcessInfo::decode_length_delimited(fs::read(&path).ok()?).ok()?;
+            if is_alive(info.pid) {
+                Some(info)
+            } else {
+                let _ = fs::remove_file(&path);
+                None
+            }
+        })
+        .collect();
+    processes.sort_by_key(|info| info.pid);
+    processes
+}
+
+/// Lets the user choose one of `processes` on the terminal
+///
+/// Returns `None`, if the input is empty or invalid.
+pub fn pick(processes: &[ProcessInfo]) -> io::Result<Option<&ProcessInfo>> {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    writeln!(stdout, "Discovered processes:")?;
+    for (i, info) in processes.iter().enumerate() {
+        writeln!(
+            stdout,
+            "  [{}] {} (pid {}) on {}, started {} ago",
+            i + 1,
+            info.exe,
+            info.pid,
+            info.addr,
+            format_age(info.started.as_ref()),
+        )?;
+    }
+    write!(stdout, "Attach to [1-{}]: ", processes.len())?;
+    stdout.flush()?;
+
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line)?;
+    Ok(line
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| processes.get(n.checked_sub(1)?)))
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .map(|name| name.to_string_lossy().starts_with('.'))
+        .unwrap_or(true)
+}
+
+#[cfg(target_os = "linux")]
+fn is_alive(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_alive(_pid: u32) -> bool {
+    // No cheap way to check, let the connection attempt decide
+    true
+}
+
+fn format_age(started: Option<&Timestamp>) -> String {
+    let started = match started {
+        Some(started) if started.nano > 0 => UNIX_EPOCH + Duration::from_nanos(started.nano as u64),
+        _ => return "?".to_string(),
+    };
+    let secs = SystemTime::now()
+        .duration_since(started)
+        .map(|age| age.as_secs())
+        .unwrap_or(0);
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m", secs / 60),
+        _ => format!("{}h{}m", secs / 3600, secs % 3600 / 60),
+    }
+}
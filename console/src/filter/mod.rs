@@ -1,15 +1,18 @@
-use crate::storage::EventEntry;
+use crate::storage::{value, EventEntry, FieldIndex, Store};
 
 use std::fmt::{Display, Formatter, Result};
+use std::ops::Range;
 
 use indexmap::IndexMap;
 
 use regex::Regex;
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub(crate) struct Filter {
     pub(crate) name: String,
     pub(crate) modifier: IndexMap<String, Modifier>,
+    /// Applied to the fields of the event's span, or the nearest ancestor having them
+    pub(crate) span_modifier: IndexMap<String, Modifier>,
 }
 
 impl Filter {
@@ -23,10 +26,47 @@ impl Filter {
         );
     }
 
-    pub(crate) fn filter(&self, entry: &EventEntry) -> bool {
+    pub(crate) fn insert_span_modifier(&mut self, modifier: Modifier) {
+        self.span_modifier.insert(
+            modifier
+                .field_name()
+                .expect("BUG: No field name found!")
+                .to_string(),
+            modifier,
+        );
+    }
+
+    pub(crate) fn filter(&self, store: &Store, entry: &EventEntry) -> bool {
         self.modifier
             .values()
             .all(|m| m.filter(entry).unwrap_or(false))
+            && self
+                .span_modifier
+                .values()
+                .all(|m| m.filter_span(store, entry).unwrap_or(false))
+    }
+
+    /// Events within `range` which might pass, if a modifier is on an indexed field
+    ///
+    /// See `StoreHandle::index_field`, the candidates still have to pass `filter`.
+    pub(crate) fn candidates(&self, store: &Store, range: Range<usize>) -> Option<Vec<usize>> {
+        let mut candidates: Option<Vec<usize>> = None;
+        for modifier in self.modifier.values() {
+            let name = modifier.field_name().expect("BUG: No field name found!");
+            let index = match store.field_index(name) {
+                Some(index) => index,
+                None => continue,
+            };
+            let positions = modifier.candidates(index, range.clone());
+            candidates = Some(match candidates {
+                Some(mut candidates) => {
+                    candidates.retain(|position| positions.binary_search(position).is_ok());
+                    candidates
+                }
+                None => positions,
+            });
+        }
+        candidates
     }
 }
 
@@ -44,23 +84,21 @@ impl Display for Modifier {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         match self {
             Modifier::FieldContains { name, value } => {
-                write!(f, "event.field.{} contains \"{}\"", name, value)
-            }
-            Modifier::FieldEquals { name, value } => {
-                write!(f, "event.field.{} == \"{}\"", name, value)
+                write!(f, "field.{} contains \"{}\"", name, value)
             }
+            Modifier::FieldEquals { name, value } => write!(f, "field.{} == \"{}\"", name, value),
             Modifier::FieldMatches { name, regex } => {
-                write!(f, "event.field.{} matches \"{}\"", name, regex)
+                write!(f, "field.{} matches \"{}\"", name, regex)
             }
             Modifier::FieldStartsWith { name, value } => {
-                write!(f, "event.field.{} starts_with \"{}\"", name, value)
+                write!(f, "field.{} starts_with \"{}\"", name, value)
             }
         }
     }
 }
 
 impl Modifier {
-    fn field_name(&self) -> Option<&str> {
+    pub(crate) fn field_name(&self) -> Option<&str> {
         match self {
             Modifier::FieldContains { name, .. } => Some(&name),
             Modifier::FieldEquals { name, .. } => Some(&name),
@@ -70,22 +108,38 @@ impl Modifier {
     }
 
     fn filter(&self, entry: &EventEntry) -> Option<bool> {
+        match entry.event.value_by_name(self.field_name()?)? {
+            value::Value::Str(string) => self.is_match(string),
+            value => self.is_match(&value.to_string()),
+        }
+    }
+
+    /// Events within `range` whose field matches, checking each distinct value once
+    fn candidates(&self, index: &FieldIndex, range: Range<usize>) -> Vec<usize> {
         match self {
-            Modifier::FieldStartsWith { name, value } => entry
-                .event
-                .any_by_name(name)
-                .map(|string| string.starts_with(value)),
-            Modifier::FieldEquals { name, value } => {
-                entry.event.any_by_name(name).map(|string| &string == value)
+            Modifier::FieldEquals { value, .. } => index.equal(value, range).to_vec(),
+            Modifier::FieldMatches { regex, .. } => match Regex::new(regex) {
+                Ok(re) => index.matching(|value| re.is_match(value), range),
+                Err(_) => Vec::new(),
+            },
+            _ => index.matching(|value| self.is_match(value) == Some(true), range),
+        }
+    }
+
+    /// Matches the effective field values of the event's span, see `Store::inherited_field`
+    fn filter_span(&self, store: &Store, entry: &EventEntry) -> Option<bool> {
+        let value = store.inherited_field(entry.span?, self.field_name()?)?;
+        self.is_match(&value.to_string())
+    }
+
+    fn is_match(&self, string: &str) -> Option<bool> {
+        match self {
+            Modifier::FieldStartsWith { value, .. } => Some(string.starts_with(value.as_str())),
+            Modifier::FieldEquals { value, .. } => Some(string == value.as_str()),
+            Modifier::FieldContains { value, .. } => Some(string.contains(value.as_str())),
+            Modifier::FieldMatches { regex, .. } => {
+                Regex::new(regex).ok().map(|re| re.is_match(string))
             }
-            Modifier::FieldContains { name, value } => entry
-                .event
-                .any_by_name(name)
-                .map(|string| string.contains(value)),
-            Modifier::FieldMatches { name, regex } => entry
-                .event
-                .any_by_name(name)
-                .and_then(|string| Regex::new(regex).ok().map(|re| re.is_match(&string))),
         }
     }
 
@@ -120,7 +174,11 @@ mod tests {
             }),
             value: Some(value::Value::Str("barbazboz".to_string())),
         });
-        EventEntry { span: None, event }
+        EventEntry {
+            source: SourceId::default(),
+            span: None,
+            event,
+        }
     }
 
     #[test]
@@ -169,4 +227,43 @@ mod tests {
         let matches = Modifier::starts_with("foo".to_string(), "bar".to_string());
         assert_eq!(matches.filter(&entry), Some(true));
     }
+
+    #[test]
+    fn modifier_span() {
+        let handle = StoreHandle::new();
+        let field = |name: &str, value: &str| Value {
+            field: Some(Field {
+                name: name.to_string(),
+            }),
+            value: Some(value::Value::Str(value.to_string())),
+        };
+        handle.handle(listen_response::Variant::NewSpan(NewSpan {
+            span: Some(SpanId { id: 1 }),
+            values: vec![field("user", "ferris"), field("status", "pending")],
+            ..NewSpan::default()
+        }));
+        handle.handle(listen_response::Variant::NewSpan(NewSpan {
+            span: Some(SpanId { id: 2 }),
+            parent: Some(SpanId { id: 1 }),
+            ..NewSpan::default()
+        }));
+        handle.handle(listen_response::Variant::Record(Record {
+            span: Some(SpanId { id: 1 }),
+            values: vec![field("status", "done")],
+            timestamp: Some(Timestamp { nano: 10 }),
+            ..Record::default()
+        }));
+        let store = handle.0.lock().unwrap();
+        let mut entry = event_entry();
+        entry.span = Some(store.spans()[1].id());
+
+        let inherited = Modifier::equals("user".to_string(), "ferris".to_string());
+        assert_eq!(inherited.filter_span(&store, &entry), Some(true));
+
+        let outdated = Modifier::equals("status".to_string(), "pending".to_string());
+        assert_eq!(outdated.filter_span(&store, &entry), Some(false));
+
+        let doesnt_exist = Modifier::equals("foo".to_string(), "barbazboz".to_string());
+        assert_eq!(doesnt_exist.filter_span(&store, &entry), None);
+    }
 }
@@ -1,4 +1,8 @@
+pub mod collector;
 pub mod connection;
+pub mod discovery;
 pub mod filter;
+pub mod loopback;
+pub mod recording;
 pub mod storage;
 pub mod ui;
@@ -0,0 +1,181 @@
+//! In-process connection to a subscriber, e.g. for integration tests
+//!
+//! ```rust,ignore
+//! use console::loopback;
+//! use console::storage::StoreHandle;
+//! use console_subscriber::BackgroundThreadHandle;
+//! use std::time::Duration;
+//!
+//! let handle = BackgroundThreadHandle::new();
+//! let store = StoreHandle::new();
+//! loopback::attach(store.clone(), handle.loopback());
+//!
+//! // Run the component under test with `handle.new_subscriber()`...
+//!
+//! let events = loopback::wait_for(
+//!     &store,
+//!     &[r#"event.field.message == "request done""#],
+//!     3,
+//!     Duration::from_secs(5),
+//! )?;
+//! ```
+use crate::filter::Filter;
+use crate::storage::*;
+use crate::ui::Command;
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use prost::Message;
+
+/// Feeds encoded `ListenResponse`s into the `Store` on a new thread
+///
+/// `messages` is usually `BackgroundThreadHandle::loopback` of the subscriber crate.
+/// The thread ends with `messages`, or on the first message which can't be decoded.
+pub fn attach<I>(store: StoreHandle, messages: I) -> thread::JoinHandle<Result<(), failure::Error>>
+where
+    I: IntoIterator<Item = Vec<u8>>,
+    I::IntoIter: Send + 'static,
+{
+    let messages = messages.into_iter();
+    thread::spawn(move || {
+        for message in messages {
+            let response = ListenResponse::decode(message)?;
+            store.handle_response(SourceId::default(), response);
+        }
+        Ok(())
+    })
+}
+
+/// Waits until at least `count` events match all `queries`
+///
+/// Queries use the syntax of the query view, e.g. `event.field.message == "done"`
+/// or `span.field.status == "done"`. `index <field>` indexes a field, see `StoreHandle::index_field`.
+/// Returns the matching events ordered by timestamp, or an error once `timeout` elapsed.
+/// Whenever the store changed, only the new events are checked, see `StoreHandle::watch`.
+pub fn wait_for(
+    store: &StoreHandle,
+    queries: &[&str],
+    count: usize,
+    timeout: Duration,
+) -> Result<Vec<EventEntry>, failure::Error> {
+    let mut filter = Filter::default();
+    for query in queries {
+        match query.parse() {
+            Ok(Command::Event(modifier)) => filter.insert_modifier(modifier),
+            Ok(Command::Span(modifier)) => filter.insert_span_modifier(modifier),
+            Ok(Command::Index(name)) => store.index_field(&name)?,
+            Err(()) => failure::bail!("invalid query: {}", query),
+        }
+    }
+
+    let watcher = store.watch();
+    let deadline = Instant::now() + timeout;
+    // Matching events before `checked_to`, along with their index
+    let mut matching: Vec<(usize, EventEntry)> = Vec::new();
+    let mut checked_to = 0;
+    let mut changes = watcher.take();
+    loop {
+        let (spilled, mut hot) = {
+            let store = store.0.lock().unwrap();
+            if changes.spans && !filter.span_modifier.is_empty() {
+                // Events might match other span fields now
+                matching.clear();
+                checked_to = 0;
+            }
+            let first_event = store.first_event();
+            matching.retain(|(index, _)| *index >= first_event);
+            let range = checked_to.max(first_event)..store.event_count();
+            checked_to = range.end;
+            // Indexed fields narrow down the events to check, see `StoreHandle::index_field`
+            let candidates = filter.candidates(&store, range.clone());
+            let hot_start = store.cold_events();
+            let hot: Vec<(usize, EventEntry)> = match &candidates {
+                Some(candidates) => candidates
+                    .iter()
+                    .filter(|&&index| index >= hot_start)
+                    .map(|&index| (index, &store.hot_events()[index - hot_start]))
+                    .filter(|(_, entry)| filter.filter(&store, entry))
+                    .map(|(index, entry)| (index, entry.clone()))
+                    .collect(),
+                None => (hot_start..)
+                    .zip(store.hot_events())
+                    .skip(range.start.saturating_sub(hot_start))
+                    .filter(|(_, entry)| filter.filter(&store, entry))
+                    .map(|(index, entry)| (index, entry.clone()))
+                    .collect(),
+            };
+            (store.spilled_events(range, candidates), hot)
+        };
+        // Spilled events match as well, they are read without holding the lock
+        let mut spilled = spilled.read()?;
+        if !spilled.is_empty() {
+            let store = store.0.lock().unwrap();
+            spilled.retain(|(_, entry)| filter.filter(&store, entry));
+        }
+        matching.append(&mut spilled);
+        matching.append(&mut hot);
+
+        if matching.len() >= count {
+            sort_timeline(&mut matching);
+            return Ok(matching.into_iter().map(|(_, entry)| entry).collect());
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            failure::bail!(
+                "timed out after {:?}: {} of {} events matched",
+                timeout,
+                matching.len(),
+                count
+            );
+        }
+        changes = watcher.wait_timeout(deadline - now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use console_subscriber::BackgroundThreadHandle;
+
+    use tracing::info;
+
+    #[test]
+    fn wait_for_events() {
+        let handle = BackgroundThreadHandle::new();
+        let store = StoreHandle::new();
+        attach(store.clone(), handle.loopback());
+
+        tracing::subscriber::with_default(handle.new_subscriber(), || {
+            for i in 0..3 {
+                info!(task = "loopback", i = i);
+            }
+            info!(task = "other");
+        });
+
+        let timeout = Duration::from_secs(5);
+        let events = wait_for(&store, &[r#"event.field.task == "loopback""#], 3, timeout).unwrap();
+        assert_eq!(events.len(), 3);
+        let events = wait_for(&store, &[r#"event.field.i == "2""#], 1, timeout).unwrap();
+        assert_eq!(
+            events[0].event.any_by_name("task"),
+            Some("loopback".to_string())
+        );
+    }
+
+    #[test]
+    fn wait_for_timeout() {
+        let handle = BackgroundThreadHandle::new();
+        let store = StoreHandle::new();
+        attach(store.clone(), handle.loopback());
+
+        tracing::subscriber::with_default(handle.new_subscriber(), || {
+            info!(task = "loopback");
+        });
+
+        let timeout = Duration::from_millis(100);
+        assert!(wait_for(&store, &[r#"event.field.task == "loopback""#], 2, timeout).is_err());
+        assert!(wait_for(&store, &["event.field.task"], 1, timeout).is_err());
+    }
+}
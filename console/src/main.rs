@@ -1,18 +1,77 @@
+use std::path::Path;
 use std::thread;
 
 use console::storage::*;
 use console::ui;
 
+const DEFAULT_ADDR: &str = "http://[::1]:50051";
+
 fn main() -> Result<(), failure::Error> {
     // Share store between the gRPC client and the app
     let grpc_handle = StoreHandle::default();
     let app_handle = grpc_handle.clone();
+    // Long sessions would exhaust memory otherwise
+    grpc_handle
+        .0
+        .lock()
+        .unwrap()
+        .spill_to(SpillConfig::default())?;
+    let store = grpc_handle.clone();
+
+    let result = run(grpc_handle, app_handle);
+    // Listen threads keep the store alive until the process exits, it's never dropped
+    store.0.lock().unwrap().remove_spilled();
+    result
+}
 
-    // Fetch events, spans, etc.
-    thread::spawn(|| console::connection::listen(grpc_handle, "http://[::1]:50051"));
+/// Feeds the store as requested by the arguments and runs the UI until it's closed
+fn run(grpc_handle: StoreHandle, app_handle: StoreHandle) -> Result<(), failure::Error> {
+    let mut args = std::env::args().skip(1);
+    match args.next() {
+        Some(ref flag) if flag == "--file" => {
+            // Browse a recording instead of a live session
+            let path = args
+                .next()
+                .ok_or_else(|| failure::err_msg("Usage: console --file <path>"))?;
+            console::recording::replay(grpc_handle, Path::new(&path))?;
+        }
+        Some(ref flag) if flag == "--accept" => {
+            // Wait for subscribers in push mode, instead of connecting to one
+            let addr = args
+                .next()
+                .ok_or_else(|| failure::err_msg("Usage: console --accept <addr>"))?;
+            thread::spawn(move || console::collector::accept(grpc_handle, &addr));
+        }
+        addr => {
+            // Any number of processes, e.g. a client and a server side by side
+            let mut addrs: Vec<String> = addr.into_iter().chain(args).collect();
+            if addrs.is_empty() {
+                addrs.push(discover()?);
+            }
+            // Fetch events, spans, etc.
+            for addr in addrs {
+                let handle = grpc_handle.clone();
+                thread::spawn(move || console::connection::listen(handle, &addr));
+            }
+        }
+    }
 
     let mut app = ui::App::new(app_handle)?;
     app.run()?;
 
     Ok(())
 }
+
+/// Attaches to a discoverable process, see `console::discovery`
+///
+/// Asks the user if there are multiple, falls back to `DEFAULT_ADDR` if there are none.
+fn discover() -> Result<String, failure::Error> {
+    let processes = console::discovery::discover();
+    let info = match processes.len() {
+        0 => return Ok(DEFAULT_ADDR.to_string()),
+        1 => &processes[0],
+        _ => console::discovery::pick(&processes)?
+            .ok_or_else(|| failure::err_msg("No process selected"))?,
+    };
+    Ok(info.addr.clone())
+}
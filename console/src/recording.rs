@@ -0,0 +1,79 @@
+//! Playback of recordings created by `BackgroundThreadHandle::record_to_file`
+//!
+//! See the subscriber crate for a description of the file format.
+use crate::storage::*;
+
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use bytes::Buf;
+
+use prost::Message;
+
+const MAGIC: &[u8; 8] = b"TCONSOLE";
+const VERSION: u32 = 1;
+
+/// Loads the recording at `path` into the `Store`, as a source named `path`
+///
+/// Rotated segments (`<path>.1`, `<path>.2`, ...) are picked up as well
+/// and replayed oldest first, so the store looks like it was fed by a live session.
+///
+/// A message which can't be decoded, e.g. as the recording process died mid-write,
+/// fails the replay along with its offset. The messages before it are kept.
+pub fn replay(store: StoreHandle, path: &Path) -> Result<(), failure::Error> {
+    let source = store.add_source(&path.display().to_string());
+    let mut segments = vec![];
+    for path in segment_paths(path) {
+        let data = fs::read(&path)?;
+        let mut buf = Cursor::new(data);
+        let header =
+            read_header(&mut buf).map_err(|e| failure::format_err!("{}: {}", path.display(), e))?;
+        segments.push((header, path, buf));
+    }
+    segments.sort_by_key(|(header, _, _)| header.segment);
+
+    for (i, (header, path, mut buf)) in segments.into_iter().enumerate() {
+        // Open spans are repeated, the previous segment knows them already
+        let mut skip = if i > 0 { header.live_spans } else { 0 };
+        while buf.has_remaining() {
+            let offset = buf.position();
+            let response = ListenResponse::decode_length_delimited(&mut buf).map_err(|e| {
+                failure::format_err!("{}: offset {}: {}", path.display(), offset, e)
+            })?;
+            if skip > 0 {
+                skip -= 1;
+                continue;
+            }
+            store.handle_response(source, response);
+        }
+    }
+    Ok(())
+}
+
+fn read_header(buf: &mut Cursor<Vec<u8>>) -> Result<FileHeader, failure::Error> {
+    if buf.remaining() < MAGIC.len() || &buf.bytes()[..MAGIC.len()] != MAGIC {
+        failure::bail!("not a console recording");
+    }
+    buf.advance(MAGIC.len());
+    let header = FileHeader::decode_length_delimited(&mut *buf)?;
+    if header.version != VERSION {
+        failure::bail!("unsupported recording version {}", header.version);
+    }
+    Ok(header)
+}
+
+/// `path` and all of its existing rotated segments
+fn segment_paths(path: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![path.to_path_buf()];
+    for n in 1.. {
+        let mut name = path.as_os_str().to_owned();
+        name.push(format!(".{}", n));
+        let rotated = PathBuf::from(name);
+        if !rotated.exists() {
+            break;
+        }
+        paths.push(rotated);
+    }
+    paths
+}
@@ -0,0 +1,71 @@
+//! Effective field values of a span
+//!
+//! A span starts out with the values of its `NewSpan`, each `Record` then
+//! overrides some of them. Earlier values are kept, to show how a span progressed.
+use crate::storage::messages::{value, Value};
+
+use indexmap::IndexMap;
+
+/// A value a field took on at `nano`
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldChange {
+    pub nano: i64,
+    pub value: value::Value,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SpanFields {
+    /// Changes of each field ordered by timestamp, the last one is in effect
+    fields: IndexMap<String, Vec<FieldChange>>,
+}
+
+impl SpanFields {
+    pub(crate) fn set(&mut self, nano: i64, values: &[Value]) {
+        for value in values {
+            let (name, value) = match (&value.field, &value.value) {
+                (Some(field), Some(value)) => (&field.name, value),
+                _ => continue,
+            };
+            let changes = self.fields.entry(name.clone()).or_insert_with(Vec::new);
+            // Records might arrive before the `NewSpan` they belong to
+            let i = changes
+                .iter()
+                .rposition(|change| change.nano <= nano)
+                .map_or(0, |i| i + 1);
+            changes.insert(
+                i,
+                FieldChange {
+                    nano,
+                    value: value.clone(),
+                },
+            );
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&value::Value> {
+        self.history(name).last().map(|change| &change.value)
+    }
+
+    /// Values in effect, in order of the first appearance of each field
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &value::Value)> {
+        self.fields
+            .iter()
+            .filter_map(|(name, changes)| Some((name.as_str(), &changes.last()?.value)))
+    }
+
+    /// All values `name` took on, oldest first
+    pub fn history(&self, name: &str) -> &[FieldChange] {
+        self.fields
+            .get(name)
+            .map(|changes| changes.as_slice())
+            .unwrap_or(&[])
+    }
+
+    pub fn any_by_name(&self, name: &str) -> Option<String> {
+        self.get(name).map(ToString::to_string)
+    }
+}
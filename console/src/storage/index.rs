@@ -0,0 +1,125 @@
+//! Inverted indexes from field values to event positions, see `StoreHandle::index_field`
+//!
+//! Values are indexed as formatted by `Event::any_by_name`, which is what filters match.
+//! Positions are event indices of the `Store`, they are kept ascending.
+use crate::storage::segment::SegmentFile;
+
+use std::collections::HashMap;
+use std::io;
+use std::ops::Range;
+
+/// Evicted positions are only dropped, once there are at least this many
+const PRUNE_EVENTS: usize = 10_000;
+
+#[derive(Debug, Default)]
+pub struct FieldIndex {
+    values: HashMap<String, Vec<usize>>,
+    /// Positions before were dropped already
+    pruned_to: usize,
+}
+
+impl FieldIndex {
+    /// Positions within `range` of events where the field is `value`
+    pub fn equal(&self, value: &str, range: Range<usize>) -> &[usize] {
+        match self.values.get(value) {
+            Some(positions) => within(positions, range),
+            None => &[],
+        }
+    }
+
+    /// Positions within `range` of events where the field matches `f`, ascending
+    ///
+    /// `f` is called once per distinct value.
+    pub fn matching<F>(&self, f: F, range: Range<usize>) -> Vec<usize>
+    where
+        F: Fn(&str) -> bool,
+    {
+        let mut positions: Vec<usize> = self
+            .values
+            .iter()
+            .filter(|(value, _)| f(value))
+            .flat_map(|(_, positions)| within(positions, range.clone()).iter().cloned())
+            .collect();
+        positions.sort();
+        positions
+    }
+
+    /// Number of distinct values
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Adds the event at `position`, which follows all positions of this index
+    pub(crate) fn insert(&mut self, position: usize, value: Option<String>) {
+        if let Some(value) = value {
+            let positions = self.values.entry(value).or_insert_with(Vec::new);
+            debug_assert!(positions.last().map_or(true, |&last| last < position));
+            positions.push(position);
+        }
+    }
+
+    /// Adds the positions of `older`, which all precede the ones of this index
+    pub(crate) fn prepend(&mut self, older: FieldIndex) {
+        for (value, mut positions) in older.values {
+            let newer = self.values.entry(value).or_insert_with(Vec::new);
+            positions.append(newer);
+            *newer = positions;
+        }
+    }
+
+    /// Drops the positions of evicted events, see `Store::first_event`
+    pub(crate) fn evicted(&mut self, first_event: usize) {
+        if first_event < self.pruned_to + PRUNE_EVENTS {
+            return;
+        }
+        self.values.retain(|_, positions| {
+            let evicted = lower_bound(positions, first_event);
+            positions.drain(..evicted);
+            !positions.is_empty()
+        });
+        self.pruned_to = first_event;
+    }
+}
+
+/// Indexes a field of the spilled events, see `Store::start_index`
+///
+/// Runs without holding the `Store`, the segments are read one at a time.
+#[derive(Debug)]
+pub struct IndexBuild {
+    pub(crate) name: String,
+    pub(crate) segments: Vec<SegmentFile>,
+}
+
+impl IndexBuild {
+    /// The index of the spilled events, segments evicted in the meantime are skipped
+    pub fn run(&self) -> io::Result<FieldIndex> {
+        let mut index = FieldIndex::default();
+        for segment in &self.segments {
+            let events = match segment.read()? {
+                Some(events) => events,
+                None => continue,
+            };
+            for (position, entry) in (segment.first()..).zip(&events) {
+                index.insert(position, entry.event.any_by_name(&self.name));
+            }
+        }
+        Ok(index)
+    }
+}
+
+fn within(positions: &[usize], range: Range<usize>) -> &[usize] {
+    let from = lower_bound(positions, range.start);
+    let to = lower_bound(positions, range.end).max(from);
+    &positions[from..to]
+}
+
+/// Index of the first position at or after `position`
+fn lower_bound(positions: &[usize], position: usize) -> usize {
+    match positions.binary_search(&position) {
+        Ok(i) | Err(i) => i,
+    }
+}
@@ -0,0 +1,63 @@
+//! Timing and state of spans
+//!
+//! Subscribers report the close and busy time of a span with `SpanClose`.
+//! Failing that, e.g. for older subscribers, timings are inferred from the activity
+//! within the span: it was entered for the events it contains, and it's closed
+//! once the subscriber reuses its id, after its last activity at the earliest.
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpanState {
+    /// No activity within the span yet
+    Open,
+    /// Entered before, but not for the most recent event
+    Idle,
+    /// The most recent event happened within the span or one of its descendants
+    Entered,
+    Closed,
+}
+
+/// Timestamps in nanoseconds, see `Store::lifecycle`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Lifecycle {
+    pub created_at: i64,
+    pub first_entered: Option<i64>,
+    /// Latest event within the span
+    pub last_active: Option<i64>,
+    pub closed_at: Option<i64>,
+    /// Total time entered, if reported by the subscriber
+    pub busy_nanos: Option<u64>,
+}
+
+impl Lifecycle {
+    /// Time from creation until the span closed, or until `now` for open ones
+    pub fn duration(&self, now: i64) -> Duration {
+        nanos(self.closed_at.unwrap_or(now) - self.created_at)
+    }
+
+    /// Total time entered, approximated by first and last activity unless reported
+    pub fn busy(&self) -> Option<Duration> {
+        match (self.busy_nanos, self.first_entered, self.last_active) {
+            (Some(busy), _, _) => Some(Duration::from_nanos(busy)),
+            (None, Some(first), Some(last)) => Some(nanos(last - first)),
+            _ => None,
+        }
+    }
+
+    /// Whether the subscriber sent a `SpanClose`, timings are inferred otherwise
+    pub fn is_reported(&self) -> bool {
+        self.busy_nanos.is_some()
+    }
+
+    /// Activity within the span at `nano`
+    pub(crate) fn activity(&mut self, nano: i64) {
+        if !self.is_reported() && self.first_entered.map_or(true, |first| nano < first) {
+            self.first_entered = Some(nano);
+        }
+        self.last_active = Some(self.last_active.map_or(nano, |last| last.max(nano)));
+    }
+}
+
+fn nanos(nanos: i64) -> Duration {
+    Duration::from_nanos(nanos.max(0) as u64)
+}
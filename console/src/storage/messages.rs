@@ -1,6 +1,8 @@
 //! Types generated by gRPC "/proto/tracing.proto"
 include!(concat!(env!("OUT_DIR"), "/tracing.rs"));
 
+use std::fmt;
+
 impl Event {
     pub fn value_by_name(&self, name: &str) -> Option<&value::Value> {
         for value in &self.values {
@@ -42,12 +44,18 @@ impl Event {
         }
     }
     pub fn any_by_name(&self, name: &str) -> Option<String> {
-        Some(match self.value_by_name(name)? {
-            value::Value::Str(string) => string.clone(),
-            value::Value::Signed(i) => format!("{}", i),
-            value::Value::Unsigned(u) => format!("{}", u),
-            value::Value::Debug(d) => d.debug.clone(),
-            value::Value::Boolean(b) => format!("{}", b),
-        })
+        self.value_by_name(name).map(ToString::to_string)
+    }
+}
+
+impl fmt::Display for value::Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            value::Value::Str(string) => f.write_str(string),
+            value::Value::Signed(i) => write!(f, "{}", i),
+            value::Value::Unsigned(u) => write!(f, "{}", u),
+            value::Value::Debug(d) => f.write_str(&d.debug),
+            value::Value::Boolean(b) => write!(f, "{}", b),
+        }
     }
 }
@@ -1,5 +1,19 @@
+mod fields;
+mod index;
+mod lifecycle;
 pub mod messages;
+mod retention;
+mod segment;
 mod store;
+mod tree;
+mod watch;
 
+pub use fields::{FieldChange, SpanFields};
+pub use index::{FieldIndex, IndexBuild};
+pub use lifecycle::{Lifecycle, SpanState};
 pub use messages::*;
+pub use retention::Retention;
+pub use segment::{SpillConfig, SpilledEvents};
 pub use store::*;
+pub use tree::Descendants;
+pub use watch::{Changes, Watcher};
@@ -0,0 +1,25 @@
+//! Bounds on the history kept by the `Store`
+//!
+//! See `Store::retain`. Limits apply to everything the store still holds,
+//! in memory or spilled to disk. The oldest events are evicted first,
+//! spilled ones in whole segments. Spans are evicted once they are closed
+//! and neither a retained event nor a panic refers to them, so no `InternalId`
+//! handed out by the store dangles. Open spans are never evicted.
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Retention {
+    pub max_events: Option<usize>,
+    pub max_spans: Option<usize>,
+    /// Relative to the latest timestamp received, not to the wall clock
+    pub max_age: Option<Duration>,
+    /// Encoded size of all events and spans
+    pub max_bytes: Option<usize>,
+}
+
+impl Retention {
+    /// Whether any limit is set, history is kept forever otherwise
+    pub fn is_limited(&self) -> bool {
+        *self != Retention::default()
+    }
+}
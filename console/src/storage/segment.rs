@@ -0,0 +1,515 @@
+//! Append-only segment files, holding events and spans evicted from memory
+//!
+//! See `Store::spill_to`. An event segment holds a batch of consecutive events,
+//! a span segment the spans which were closed in the meantime.
+//! Each entry is encoded by `EventEntry::encode` and `Span::encode` respectively.
+//!
+//! The index stays in memory: the event range, timestamps and span references of
+//! each event segment and the location of each spilled span. Event segments are paged
+//! in as a whole, the most recently used ones are cached. Spans are read one by one.
+//!
+//! Retention evicts whole event segments and single spans, see `Retention`.
+//! A span segment is removed once all of its spans were evicted.
+use crate::storage::*;
+
+use std::cell::RefCell;
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::fs::{self, File};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+
+use bytes::Buf;
+
+/// Number of paged in event segments, which are kept around
+const CACHED_SEGMENTS: usize = 4;
+
+/// Controls when the `Store` moves data to disk
+#[derive(Clone, Debug)]
+pub struct SpillConfig {
+    /// Directory of the segment files, only the current user may access it
+    ///
+    /// The segment files are removed once the `Store` is dropped,
+    /// the directory only if it didn't exist before.
+    pub dir: PathBuf,
+    /// Events kept in memory, older ones are spilled in batches of `segment_events`
+    pub hot_events: usize,
+    pub segment_events: usize,
+    /// Closed spans kept in memory, before they are spilled together
+    pub closed_spans: usize,
+}
+
+impl Default for SpillConfig {
+    fn default() -> SpillConfig {
+        SpillConfig {
+            dir: env::temp_dir().join(format!(
+                "tracing-console-spill-{}-{:x}",
+                process::id(),
+                nonce()
+            )),
+            hot_events: 100_000,
+            segment_events: 10_000,
+            closed_spans: 10_000,
+        }
+    }
+}
+
+/// A random number, spill directories must not be predictable in the shared temporary directory
+fn nonce() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+#[derive(Debug)]
+struct EventSegment {
+    path: PathBuf,
+    /// Index of the first event
+    first: usize,
+    len: usize,
+    /// Earliest and latest timestamp within the segment
+    min_nano: i64,
+    max_nano: i64,
+    /// Encoded size of the events
+    bytes: usize,
+    /// Number of events referencing each span
+    spans: HashMap<InternalId, usize>,
+}
+
+/// An event segment, which can be read without holding the `Store`, see `IndexBuild`
+#[derive(Clone, Debug)]
+pub(crate) struct SegmentFile {
+    path: PathBuf,
+    /// Index of the first event
+    first: usize,
+    len: usize,
+}
+
+impl SegmentFile {
+    pub(crate) fn first(&self) -> usize {
+        self.first
+    }
+
+    /// All events of the segment, `None` if it was evicted in the meantime
+    pub(crate) fn read(&self) -> io::Result<Option<Vec<EventEntry>>> {
+        match read_events(&self.path, self.len) {
+            Ok(events) => Ok(Some(events)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Spilled events, which are read without holding the `Store`, see `Store::spilled_events`
+#[derive(Debug, Default)]
+pub struct SpilledEvents {
+    segments: Vec<SegmentFile>,
+    range: Range<usize>,
+    /// Only the events at these positions, ascending
+    positions: Option<Vec<usize>>,
+}
+
+impl SpilledEvents {
+    /// The events along with their index, segments evicted in the meantime are skipped
+    pub fn read(&self) -> io::Result<Vec<(usize, EventEntry)>> {
+        let mut events = Vec::new();
+        for segment in &self.segments {
+            let entries = match segment.read()? {
+                Some(entries) => entries,
+                None => continue,
+            };
+            let within = (segment.first..)
+                .zip(entries)
+                .filter(|(p, _)| self.range.start <= *p && *p < self.range.end);
+            match &self.positions {
+                Some(positions) => {
+                    events.extend(within.filter(|(p, _)| positions.binary_search(p).is_ok()))
+                }
+                None => events.extend(within),
+            }
+        }
+        Ok(events)
+    }
+}
+
+/// An event segment which was evicted
+#[derive(Debug)]
+pub(crate) struct Evicted {
+    pub(crate) bytes: usize,
+    pub(crate) spans: HashMap<InternalId, usize>,
+}
+
+/// Where a spilled span is encoded
+#[derive(Debug, Clone, Copy)]
+struct SpanLocation {
+    /// Index into `Segments::span_segments`
+    segment: usize,
+    offset: u64,
+    len: usize,
+}
+
+#[derive(Debug)]
+struct SpanSegment {
+    path: PathBuf,
+    /// Spans which weren't evicted yet
+    live: usize,
+}
+
+#[derive(Debug)]
+pub(crate) struct Segments {
+    config: SpillConfig,
+    /// Failed writes disable spilling, already spilled data stays available
+    enabled: bool,
+    /// The directory didn't exist before, it's removed along with the segments
+    created: bool,
+    events: Vec<EventSegment>,
+    span_segments: Vec<SpanSegment>,
+    spans: HashMap<InternalId, SpanLocation>,
+    /// Paged in event segments by their first event, most recently used last
+    cache: RefCell<VecDeque<(usize, Arc<Vec<EventEntry>>)>>,
+}
+
+impl Segments {
+    pub(crate) fn create(config: SpillConfig) -> io::Result<Segments> {
+        let created = !config.dir.exists();
+        create_private(&config.dir)?;
+        Ok(Segments {
+            config,
+            enabled: true,
+            created,
+            events: Vec::new(),
+            span_segments: Vec::new(),
+            spans: HashMap::new(),
+            cache: RefCell::new(VecDeque::new()),
+        })
+    }
+
+    pub(crate) fn config(&self) -> &SpillConfig {
+        &self.config
+    }
+
+    pub(crate) fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub(crate) fn disable(&mut self) {
+        self.enabled = false;
+    }
+
+    /// Index of the first spilled event, which wasn't evicted
+    pub(crate) fn first_event(&self) -> Option<usize> {
+        self.events.first().map(|segment| segment.first)
+    }
+
+    /// Earliest and latest timestamp of the oldest event segment
+    pub(crate) fn oldest_nanos(&self) -> Option<(i64, i64)> {
+        self.events
+            .first()
+            .map(|segment| (segment.min_nano, segment.max_nano))
+    }
+
+    pub(crate) fn span_count(&self) -> usize {
+        self.spans.len()
+    }
+
+    pub(crate) fn is_spilled(&self, id: InternalId) -> bool {
+        self.spans.contains_key(&id)
+    }
+
+    /// Events within `range`, only those at `positions` if any, see `SpilledEvents::read`
+    ///
+    /// Only the segments containing any of them are read.
+    pub(crate) fn spilled_events(
+        &self,
+        range: Range<usize>,
+        positions: Option<Vec<usize>>,
+    ) -> SpilledEvents {
+        let segments = self
+            .event_files()
+            .into_iter()
+            .filter(|segment| {
+                let end = segment.first + segment.len;
+                match &positions {
+                    Some(positions) => positions.iter().any(|&p| segment.first <= p && p < end),
+                    None => segment.first < range.end && range.start < end,
+                }
+            })
+            .collect();
+        SpilledEvents {
+            segments,
+            range,
+            positions,
+        }
+    }
+
+    /// The event segments, oldest first
+    pub(crate) fn event_files(&self) -> Vec<SegmentFile> {
+        self.events
+            .iter()
+            .map(|segment| SegmentFile {
+                path: segment.path.clone(),
+                first: segment.first,
+                len: segment.len,
+            })
+            .collect()
+    }
+
+    /// Appends `events` from index `first` on, which directly follow the previously spilled ones
+    pub(crate) fn spill_events(&mut self, first: usize, events: &[EventEntry]) -> io::Result<()> {
+        let path = self.config.dir.join(format!("events-{}.seg", first));
+        let mut buf = Vec::new();
+        let mut spans = HashMap::new();
+        for entry in events {
+            entry.encode(&mut buf);
+            if let Some(span) = entry.span {
+                *spans.entry(span).or_insert(0) += 1;
+            }
+        }
+        write_segment(&path, &buf)?;
+
+        let timestamps = events.iter().map(EventEntry::timestamp);
+        self.events.push(EventSegment {
+            path,
+            first,
+            len: events.len(),
+            min_nano: timestamps.clone().min().unwrap_or(0),
+            max_nano: timestamps.max().unwrap_or(0),
+            bytes: events.iter().map(EventEntry::encoded_len).sum(),
+            spans,
+        });
+        Ok(())
+    }
+
+    /// Removes the oldest event segment
+    pub(crate) fn evict_events(&mut self) -> Option<Evicted> {
+        if self.events.is_empty() {
+            return None;
+        }
+        let segment = self.events.remove(0);
+        let _ = fs::remove_file(&segment.path);
+        self.cache
+            .borrow_mut()
+            .retain(|(first, _)| *first != segment.first);
+        Some(Evicted {
+            bytes: segment.bytes,
+            spans: segment.spans,
+        })
+    }
+
+    /// Forgets a spilled span, returns whether it was spilled
+    pub(crate) fn evict_span(&mut self, id: InternalId) -> bool {
+        let segment = match self.spans.remove(&id) {
+            Some(location) => &mut self.span_segments[location.segment],
+            None => return false,
+        };
+        segment.live -= 1;
+        if segment.live == 0 {
+            let _ = fs::remove_file(&segment.path);
+        }
+        true
+    }
+
+    pub(crate) fn spill_spans(&mut self, spans: &[Span]) -> io::Result<()> {
+        let segment = self.span_segments.len();
+        let path = self.config.dir.join(format!("spans-{}.seg", segment));
+        let mut buf = Vec::new();
+        let mut locations = Vec::with_capacity(spans.len());
+        for span in spans {
+            let offset = buf.len();
+            span.encode(&mut buf);
+            let location = SpanLocation {
+                segment,
+                offset: offset as u64,
+                len: buf.len() - offset,
+            };
+            locations.push((span.id(), location));
+        }
+        write_segment(&path, &buf)?;
+
+        self.span_segments.push(SpanSegment {
+            path,
+            live: spans.len(),
+        });
+        self.spans.extend(locations);
+        Ok(())
+    }
+
+    /// Spilled events within `range`
+    pub(crate) fn load_events(&self, range: Range<usize>) -> io::Result<Vec<EventEntry>> {
+        let mut events = Vec::with_capacity(range.len());
+        for segment in &self.events {
+            let end = segment.first + segment.len;
+            if end <= range.start || segment.first >= range.end {
+                continue;
+            }
+            let entries = self.page_in(segment)?;
+            let from = range.start.saturating_sub(segment.first);
+            let to = range.end.min(end) - segment.first;
+            events.extend_from_slice(&entries[from..to]);
+        }
+        Ok(events)
+    }
+
+    /// Spilled events at `positions`, which are ascending, along with their position
+    ///
+    /// Only the segments containing any of them are paged in.
+    pub(crate) fn load_positions(
+        &self,
+        positions: &[usize],
+    ) -> io::Result<Vec<(usize, EventEntry)>> {
+        let mut events = Vec::with_capacity(positions.len());
+        let mut rest = positions;
+        for segment in &self.events {
+            let end = segment.first + segment.len;
+            let before = rest.iter().take_while(|&&p| p < segment.first).count();
+            rest = &rest[before..];
+            let within = rest.iter().take_while(|&&p| p < end).count();
+            if within == 0 {
+                continue;
+            }
+            let entries = self.page_in(segment)?;
+            events.extend(
+                rest[..within]
+                    .iter()
+                    .map(|&p| (p, entries[p - segment.first].clone())),
+            );
+            rest = &rest[within..];
+        }
+        Ok(events)
+    }
+
+    /// Spilled events directly within span `id`, along with their index
+    pub(crate) fn events_in(&self, id: InternalId) -> io::Result<Vec<(usize, EventEntry)>> {
+        let mut events = Vec::new();
+        for segment in &self.events {
+            if !segment.spans.contains_key(&id) {
+                continue;
+            }
+            let entries = self.page_in(segment)?;
+            events.extend(
+                entries
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, entry)| entry.span == Some(id))
+                    .map(|(i, entry)| (segment.first + i, entry.clone())),
+            );
+        }
+        Ok(events)
+    }
+
+    /// Reads and decodes only the span itself, not its whole segment
+    pub(crate) fn load_span(&self, id: InternalId) -> io::Result<Option<Span>> {
+        let location = match self.spans.get(&id) {
+            Some(location) => *location,
+            None => return Ok(None),
+        };
+        let mut file = File::open(&self.span_segments[location.segment].path)?;
+        file.seek(SeekFrom::Start(location.offset))?;
+        let mut buf = vec![0; location.len];
+        file.read_exact(&mut buf)?;
+        let span = Span::decode(&mut Cursor::new(buf)).map_err(invalid_data)?;
+        Ok(Some(span))
+    }
+
+    /// Index of the first spilled event at or after `nano`, if there is one
+    ///
+    /// Segments are searched by their time range, events are assumed to arrive roughly in order.
+    pub(crate) fn event_at(&self, nano: i64) -> io::Result<Option<usize>> {
+        let segment = match self.events.iter().find(|segment| segment.max_nano >= nano) {
+            Some(segment) => segment,
+            None => return Ok(None),
+        };
+        let offset = self
+            .page_in(segment)?
+            .iter()
+            .position(|entry| entry.timestamp() >= nano)
+            .unwrap_or(0);
+        Ok(Some(segment.first + offset))
+    }
+
+    fn page_in(&self, segment: &EventSegment) -> io::Result<Arc<Vec<EventEntry>>> {
+        let mut cache = self.cache.borrow_mut();
+        if let Some(pos) = cache.iter().position(|(first, _)| *first == segment.first) {
+            let entry = cache.remove(pos).unwrap();
+            let entries = entry.1.clone();
+            cache.push_back(entry);
+            return Ok(entries);
+        }
+
+        let entries = Arc::new(read_events(&segment.path, segment.len)?);
+        if cache.len() == CACHED_SEGMENTS {
+            cache.pop_front();
+        }
+        cache.push_back((segment.first, entries.clone()));
+        Ok(entries)
+    }
+}
+
+impl Drop for Segments {
+    fn drop(&mut self) {
+        // Only the files written by the console, the directory might hold others
+        let spans = self.span_segments.iter().filter(|segment| segment.live > 0);
+        let paths = self
+            .events
+            .iter()
+            .map(|segment| &segment.path)
+            .chain(spans.map(|segment| &segment.path));
+        for path in paths {
+            let _ = fs::remove_file(path);
+        }
+        if self.created {
+            // Fails if anything else was put there in the meantime
+            let _ = fs::remove_dir(&self.config.dir);
+        }
+    }
+}
+
+/// Creates `dir` if needed, fails unless only the current user can access it
+///
+/// Segments hold field values of the traced processes, other users must not read them.
+fn create_private(dir: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)?;
+    }
+    #[cfg(not(unix))]
+    fs::create_dir_all(dir)?;
+    if !crate::discovery::is_private(dir) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} is accessible by other users", dir.display()),
+        ));
+    }
+    Ok(())
+}
+
+fn read_events(path: &Path, len: usize) -> io::Result<Vec<EventEntry>> {
+    let mut buf = Cursor::new(fs::read(path)?);
+    let mut entries = Vec::with_capacity(len);
+    while buf.has_remaining() {
+        entries.push(EventEntry::decode(&mut buf).map_err(invalid_data)?);
+    }
+    Ok(entries)
+}
+
+fn write_segment(path: &Path, buf: &[u8]) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let written = file.write_all(buf).and_then(|()| file.flush());
+    if written.is_err() {
+        // The segment isn't recorded, nothing else would remove it
+        let _ = fs::remove_file(path);
+    }
+    written
+}
+
+fn invalid_data(e: prost::DecodeError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
@@ -1,8 +1,25 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io;
+use std::iter;
+use std::mem;
+use std::ops::Range;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+use bytes::Buf;
+
+use prost::encoding::{decode_varint, encode_varint};
+use prost::{DecodeError, Message};
+
+use crate::storage::fields::SpanFields;
+use crate::storage::index::{FieldIndex, IndexBuild};
+use crate::storage::lifecycle::{Lifecycle, SpanState};
 use crate::storage::messages::listen_response::Variant;
 use crate::storage::messages::*;
+use crate::storage::retention::Retention;
+use crate::storage::segment::{Segments, SpillConfig, SpilledEvents};
+use crate::storage::tree::{Descendants, SpanTree};
+use crate::storage::watch::{Changes, Pending, Watcher};
 
 /// # IDs
 /// The subscriber obviously want to reuse span ids, to preserve memory
@@ -12,16 +29,104 @@ use crate::storage::messages::*;
 /// This replaces the entry in the `id_map` and a new internal id is assigned.
 ///
 /// The console itself won't reuse ids.
-/// In the future, old/unused span information will be flushed to disk.
-/// Currently, the console doesn't do such kind of memory optimization.
+///
+/// # Sources
+/// A store can be fed by many processes at once, e.g. a client and a server.
+/// Each connection registers a source with `Store::add_source`, subscriber ids
+/// are mapped per source. Messages tagged by a relay, see `ListenResponse::source`,
+/// get a source of their own. Events of all sources are kept in the order they arrived,
+/// readers merge them into one timeline, see `sort_timeline`.
+///
+/// # Anomalies
+/// Messages might refer to spans the console never saw, e.g. when it connected late,
+/// a message was dropped or messages were reordered. Unknown ids are mapped to
+/// placeholder spans, which are backfilled once their `NewSpan` arrives.
+/// Such anomalies are counted, see `Anomalies`.
+///
+/// # Span tree
+/// Spans are linked to their parent on ingest, see `Store::children`.
+/// Parents and follows-from edges are resolved to internal ids, unknown ones
+/// are placeholders as well. Like events, they keep the spans they refer to from eviction.
+///
+/// # Fields
+/// Each span has effective field values, those of its `NewSpan` overridden by later
+/// `Record`s, see `Store::fields`. Records are kept as they were received as well.
+///
+/// # Lifecycle
+/// Spans are closed by `SpanClose`, or once the subscriber reuses their id.
+/// See `Lifecycle` for how timings are inferred, if the subscriber doesn't report them.
+///
+/// # Spill
+/// By default, everything is kept in memory. With `Store::spill_to`,
+/// old events and closed spans are moved to segment files, see `segment`.
+/// Only the most recent events are kept in `events`, they are preceded by
+/// `cold_events` spilled ones. Spans are closed once the subscriber reuses their id,
+/// so they won't receive updates anymore.
+/// `load_events` and `load_span` page spilled data in on demand.
+///
+/// # Retention
+/// With `Store::retain`, the oldest events and closed spans are evicted, see `Retention`.
+/// Event indices are never reused, evicted events precede `first_event`.
+///
+/// # Changes
+/// Instead of polling `Store::updated`, views can wait for `Changes` with `StoreHandle::watch`.
+///
+/// # Field indexes
+/// Filtering on a field scans every event. With `StoreHandle::index_field`, the positions
+/// of events are kept by value of the field, see `FieldIndex`.
+/// Indexes cover spilled events as well, they are kept up to date on ingest.
+/// Spilled events are indexed without holding the store, see `Store::start_index`.
 #[derive(Debug, Default)]
 pub struct Store {
-    events: Vec<EventEntry>,
+    /// Most recent events, the older ones are spilled
+    events: VecDeque<EventEntry>,
+    /// Index of the first event in memory
+    hot_start: usize,
+    /// Spans in memory, ordered by id
     spans: Vec<Span>,
+    panics: Vec<PanicEntry>,
+    /// Most recent self-telemetry of the subscriber
+    stats: Option<SubscriberStats>,
+    /// Reported along with the stats, the most recent last
+    subscriber_errors: Vec<String>,
+    process_samples: Vec<ProcessSample>,
 
     updated: bool,
     id_counter: usize,
-    id_map: HashMap<u64, InternalId>,
+    id_map: HashMap<(SourceId, u64), InternalId>,
+    /// Names indexed by `SourceId`, the unnamed default source first
+    sources: Vec<String>,
+
+    segments: Option<Segments>,
+    /// Spans in memory, whose subscriber id was reused
+    closed: Vec<InternalId>,
+
+    retention: Retention,
+    /// Number of retained events and panics referencing each span
+    span_refs: HashMap<InternalId, usize>,
+    /// Timestamp and size of closed spans, the candidates for eviction
+    evictable: BTreeMap<InternalId, (i64, usize)>,
+    /// Encoded size of the retained events and spans
+    bytes: usize,
+    latest_nano: i64,
+    /// Spans might have become evictable since the last eviction
+    retain_spans: bool,
+
+    anomalies: Anomalies,
+    tree: SpanTree,
+    /// The span of the most recent event of each source, followed by its ancestors
+    entered: HashMap<SourceId, Vec<InternalId>>,
+
+    /// Changes by the current message, see `Store::notify`
+    changes: Changes,
+    watchers: Vec<Arc<Pending>>,
+
+    /// Indexes by field name, see `StoreHandle::index_field`
+    indexes: HashMap<String, FieldIndex>,
+    /// Indexes whose spilled events are still being read, see `Store::start_index`
+    building: HashMap<String, FieldIndex>,
+    /// Most recent failure to build an index
+    index_error: Option<String>,
 }
 
 impl Store {
@@ -37,30 +142,440 @@ impl Store {
         self.updated = false;
     }
 
-    pub fn events(&self) -> &[EventEntry] {
+    /// Registers a connection, the source of a name which was added before is reused
+    pub fn add_source(&mut self, name: &str) -> SourceId {
+        if self.sources.is_empty() {
+            self.sources.push(String::new());
+        }
+        if let Some(i) = self.sources.iter().position(|source| source == name) {
+            return SourceId(i);
+        }
+        self.sources.push(name.to_string());
+        SourceId(self.sources.len() - 1)
+    }
+
+    /// Added sources, along with their name
+    pub fn sources(&self) -> impl Iterator<Item = (SourceId, &str)> {
+        self.sources
+            .iter()
+            .enumerate()
+            .skip(1)
+            .map(|(i, name)| (SourceId(i), name.as_str()))
+    }
+
+    /// Empty for the default source, see `StoreHandle::handle`
+    pub fn source_name(&self, id: SourceId) -> &str {
+        self.sources.get(id.0).map_or("", String::as_str)
+    }
+
+    /// Moves old events and closed spans to disk, see `SpillConfig`
+    pub fn spill_to(&mut self, config: SpillConfig) -> io::Result<()> {
+        self.segments = Some(Segments::create(config)?);
+        Ok(())
+    }
+
+    /// Removes the segment files, e.g. on shutdown while other threads still hold the store
+    ///
+    /// Spilled events and spans are gone afterwards, nothing is spilled anymore.
+    pub fn remove_spilled(&mut self) {
+        self.segments = None;
+    }
+
+    /// Evicts old data from now on, see `Retention`
+    pub fn retain(&mut self, retention: Retention) {
+        self.retention = retention;
+        self.retain_spans = true;
+        self.evict();
+        self.notify();
+    }
+
+    pub fn retention(&self) -> &Retention {
+        &self.retention
+    }
+
+    /// Only the events in memory, preceded by `cold_events` spilled or evicted ones
+    ///
+    /// Without `spill_to`, these are all retained events. Otherwise, use `load_events`
+    /// to read spilled ones as well, e.g. `load_events(first_event()..event_count())`.
+    ///
+    /// Ordered by arrival, so an event keeps its index. Sources aren't in sync,
+    /// use `sort_timeline` to order events by timestamp.
+    pub fn hot_events(&self) -> &VecDeque<EventEntry> {
         &self.events
     }
 
+    /// Number of events before the ones in memory, spilled or evicted
+    pub fn cold_events(&self) -> usize {
+        self.hot_start
+    }
+
+    /// Index of the oldest retained event, the ones before were evicted
+    pub fn first_event(&self) -> usize {
+        self.segments
+            .as_ref()
+            .and_then(Segments::first_event)
+            .unwrap_or(self.hot_start)
+    }
+
+    /// Total number of events, including spilled and evicted ones
+    pub fn event_count(&self) -> usize {
+        self.hot_start + self.events.len()
+    }
+
+    /// Number of events, which weren't evicted
+    pub fn retained_events(&self) -> usize {
+        self.event_count() - self.first_event()
+    }
+
+    /// Timestamps of the oldest retained and the latest event
+    pub fn time_window(&self) -> Option<(i64, i64)> {
+        let oldest = match self.segments.as_ref().and_then(Segments::oldest_nanos) {
+            Some((min_nano, _)) => min_nano,
+            None => self.events.front()?.timestamp(),
+        };
+        Some((oldest, self.latest_nano))
+    }
+
+    /// Events within `range` of all events, spilled ones are read from disk
+    ///
+    /// Evicted events are left out, the result starts at `first_event` at the earliest.
+    pub fn load_events(&self, range: Range<usize>) -> io::Result<Vec<EventEntry>> {
+        let range = range.start.max(self.first_event())..range.end;
+        let cold = self.cold_events();
+        let mut events = match &self.segments {
+            Some(segments) if range.start < cold => {
+                segments.load_events(range.start..range.end.min(cold))?
+            }
+            _ => Vec::new(),
+        };
+        let hot = range.start.saturating_sub(cold).min(self.events.len())
+            ..range.end.saturating_sub(cold).min(self.events.len());
+        events.extend(self.events.iter().skip(hot.start).take(hot.len()).cloned());
+        Ok(events)
+    }
+
+    /// Retained events at `positions`, which are ascending, along with their position
+    ///
+    /// Only the spilled segments containing any of them are read from disk.
+    pub fn load_events_at(&self, positions: &[usize]) -> io::Result<Vec<(usize, EventEntry)>> {
+        let first_event = self.first_event();
+        let evicted = positions.iter().take_while(|&&p| p < first_event).count();
+        let positions = &positions[evicted..];
+        let cold = positions
+            .iter()
+            .take_while(|&&p| p < self.hot_start)
+            .count();
+        let mut events = match &self.segments {
+            Some(segments) => segments.load_positions(&positions[..cold])?,
+            None => Vec::new(),
+        };
+        events.extend(positions[cold..].iter().filter_map(|&p| {
+            let entry = self.events.get(p - self.hot_start)?;
+            Some((p, entry.clone()))
+        }));
+        Ok(events)
+    }
+
+    /// Spilled events within `range`, only those at `positions` if any, which are ascending
+    ///
+    /// Unlike `load_events`, they are read without holding the `Store`, see `SpilledEvents::read`.
+    pub fn spilled_events(
+        &self,
+        range: Range<usize>,
+        positions: Option<Vec<usize>>,
+    ) -> SpilledEvents {
+        let range = range.start.max(self.first_event())..range.end.min(self.hot_start);
+        match &self.segments {
+            Some(segments) if range.start < range.end => segments.spilled_events(range, positions),
+            _ => SpilledEvents::default(),
+        }
+    }
+
+    /// Index of the first event at or after `nano`, `event_count` if there is none
+    pub fn event_index_at(&self, nano: i64) -> io::Result<usize> {
+        if let Some(segments) = &self.segments {
+            if let Some(index) = segments.event_at(nano)? {
+                return Ok(index);
+            }
+        }
+        let offset = self
+            .events
+            .iter()
+            .position(|entry| entry.timestamp() >= nano)
+            .unwrap_or_else(|| self.events.len());
+        Ok(self.cold_events() + offset)
+    }
+
+    /// Spans in memory, ordered by id
     pub fn spans(&self) -> &[Span] {
         &self.spans
     }
+
+    /// Number of retained spans, including spilled ones
+    pub fn span_count(&self) -> usize {
+        self.spans.len()
+            + self
+                .segments
+                .as_ref()
+                .map(Segments::span_count)
+                .unwrap_or(0)
+    }
+
+    /// The span if it's in memory, see `load_span`
+    pub fn span(&self, id: InternalId) -> Option<&Span> {
+        self.span_index(id).map(|i| &self.spans[i])
+    }
+
+    /// The span, spilled spans are read from disk
+    pub fn load_span(&self, id: InternalId) -> io::Result<Option<Span>> {
+        match (self.span(id), &self.segments) {
+            (Some(span), _) => Ok(Some(span.clone())),
+            (None, Some(segments)) => segments.load_span(id),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Name of a retained span, placeholders don't have one yet
+    pub fn span_name(&self, id: InternalId) -> Option<&str> {
+        self.tree.name(id)
+    }
+
+    /// Explicit or contextual parent, `None` for root spans
+    pub fn parent(&self, id: InternalId) -> Option<InternalId> {
+        self.tree.parent(id)
+    }
+
+    /// Parent, grandparent and so on, up to the root
+    pub fn ancestors(&self, id: InternalId) -> impl Iterator<Item = InternalId> + '_ {
+        iter::successors(self.tree.parent(id), move |id| self.tree.parent(*id))
+    }
+
+    /// Direct children, ordered by id
+    pub fn children(&self, id: InternalId) -> &[InternalId] {
+        self.tree.children(id)
+    }
+
+    /// All spans below `id`, depth-first
+    pub fn descendants(&self, id: InternalId) -> Descendants<'_> {
+        Descendants::new(&self.tree, id)
+    }
+
+    /// Spans `id` follows from
+    pub fn follows(&self, id: InternalId) -> &[InternalId] {
+        self.tree.follows(id)
+    }
+
+    /// Retained events directly within `id`, along with their index
+    ///
+    /// Only spilled segments containing such events are read from disk.
+    pub fn events_in(&self, id: InternalId) -> io::Result<Vec<(usize, EventEntry)>> {
+        let mut events = match &self.segments {
+            Some(segments) => segments.events_in(id)?,
+            None => Vec::new(),
+        };
+        let hot_start = self.hot_start;
+        events.extend(
+            self.events
+                .iter()
+                .enumerate()
+                .filter(|(_, entry)| entry.span == Some(id))
+                .map(|(i, entry)| (hot_start + i, entry.clone())),
+        );
+        Ok(events)
+    }
+
+    /// Field values of a retained span, along with their history
+    pub fn fields(&self, id: InternalId) -> Option<&SpanFields> {
+        self.tree.fields(id)
+    }
+
+    /// Value of field `name` of the innermost span it's set on, starting at `id`
+    pub fn inherited_field(&self, id: InternalId, name: &str) -> Option<&value::Value> {
+        iter::once(id)
+            .chain(self.ancestors(id))
+            .find_map(|id| self.tree.fields(id)?.get(name))
+    }
+
+    /// Starts indexing field `name`, unless it's indexed or being indexed already
+    ///
+    /// Events in memory are indexed right away, new ones on ingest. The spilled ones
+    /// are left to `IndexBuild::run`, the index is only used after `finish_index`.
+    pub fn start_index(&mut self, name: &str) -> Option<IndexBuild> {
+        if self.indexes.contains_key(name) || self.building.contains_key(name) {
+            return None;
+        }
+        let mut index = FieldIndex::default();
+        for (position, entry) in (self.hot_start..).zip(&self.events) {
+            index.insert(position, entry.event.any_by_name(name));
+        }
+        self.building.insert(name.to_string(), index);
+        Some(IndexBuild {
+            name: name.to_string(),
+            segments: self
+                .segments
+                .as_ref()
+                .map(Segments::event_files)
+                .unwrap_or_default(),
+        })
+    }
+
+    /// Completes `start_index` with the index of the spilled events
+    ///
+    /// Spilled events don't move, their positions precede the ones indexed meanwhile.
+    /// On failure the field isn't indexed, the error is kept for `index_error`.
+    pub fn finish_index(&mut self, name: &str, spilled: io::Result<FieldIndex>) -> io::Result<()> {
+        let mut index = match self.building.remove(name) {
+            Some(index) => index,
+            None => return Ok(()),
+        };
+        match spilled {
+            Ok(spilled) => {
+                index.prepend(spilled);
+                index.evicted(self.first_event());
+                self.indexes.insert(name.to_string(), index);
+                Ok(())
+            }
+            Err(e) => {
+                self.index_error = Some(format!("indexing {} failed: {}", name, e));
+                self.changes.status = true;
+                self.notify();
+                Err(e)
+            }
+        }
+    }
+
+    /// Most recent failure of `finish_index`
+    pub fn index_error(&self) -> Option<&str> {
+        self.index_error.as_ref().map(String::as_str)
+    }
+
+    /// Index of field `name`, if it's indexed
+    pub fn field_index(&self, name: &str) -> Option<&FieldIndex> {
+        self.indexes.get(name)
+    }
+
+    /// Names of the indexed fields
+    pub fn indexed_fields(&self) -> impl Iterator<Item = &str> {
+        self.indexes.keys().map(String::as_str)
+    }
+
+    /// Timings of a retained span
+    pub fn lifecycle(&self, id: InternalId) -> Option<&Lifecycle> {
+        self.tree.lifecycle(id)
+    }
+
+    pub fn state(&self, id: InternalId) -> Option<SpanState> {
+        let lifecycle = self.tree.lifecycle(id)?;
+        Some(if lifecycle.closed_at.is_some() {
+            SpanState::Closed
+        } else if self.entered.values().any(|entered| entered.contains(&id)) {
+            SpanState::Entered
+        } else if lifecycle.first_entered.is_some() {
+            SpanState::Idle
+        } else {
+            SpanState::Open
+        })
+    }
+
+    /// Spans called `name` with the longest duration, longest first
+    ///
+    /// Open spans count until the latest timestamp received, see `Lifecycle::duration`.
+    pub fn longest_spans(&self, name: &str, limit: usize) -> Vec<(InternalId, Duration)> {
+        let mut spans: Vec<_> = self
+            .tree
+            .iter()
+            .filter(|(_, span_name, _)| *span_name == Some(name))
+            .map(|(id, _, lifecycle)| (id, lifecycle.duration(self.latest_nano)))
+            .collect();
+        spans.sort_by(|(a_id, a), (b_id, b)| b.cmp(a).then(a_id.cmp(b_id)));
+        spans.truncate(limit);
+        spans
+    }
+
+    /// Spans which are still open, at least `age` after they were created
+    ///
+    /// Relative to the latest timestamp received, ordered by id.
+    pub fn open_spans(&self, age: Duration) -> Vec<InternalId> {
+        let mut spans: Vec<_> = self
+            .tree
+            .iter()
+            .filter(|(_, _, lifecycle)| {
+                lifecycle.closed_at.is_none() && lifecycle.duration(self.latest_nano) >= age
+            })
+            .map(|(id, _, _)| id)
+            .collect();
+        spans.sort();
+        spans
+    }
+
+    pub fn anomalies(&self) -> &Anomalies {
+        &self.anomalies
+    }
+
+    pub fn panics(&self) -> &[PanicEntry] {
+        &self.panics
+    }
+
+    pub fn stats(&self) -> Option<&SubscriberStats> {
+        self.stats.as_ref()
+    }
+
+    /// Problems the subscriber reported, e.g. a recording which stopped
+    ///
+    /// Only the most recent ones are kept.
+    pub fn subscriber_errors(&self) -> &[String] {
+        &self.subscriber_errors
+    }
+
+    pub fn process_samples(&self) -> &[ProcessSample] {
+        &self.process_samples
+    }
 }
 
 /// See `Store` documentation
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct InternalId(usize);
 
-#[derive(Debug)]
+/// A connection feeding the `Store`, see `Store::add_source`
+///
+/// The default source is used by `StoreHandle::handle`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SourceId(usize);
+
+/// Unexpected messages, which were tolerated by the `Store`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Anomalies {
+    /// Messages without a required span id, they are dropped
+    pub malformed: usize,
+    /// Placeholder spans, created for unknown ids
+    pub placeholders: usize,
+    /// Placeholders which were filled in by a late `NewSpan`
+    pub backfilled: usize,
+}
+
+impl Anomalies {
+    pub fn any(&self) -> bool {
+        *self != Anomalies::default()
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Span {
     id: InternalId,
+    source: SourceId,
     span: NewSpan,
+    /// Created for an unknown id, `span` only holds the subscriber id
+    placeholder: bool,
 
+    /// Explicit or contextual parent, `None` for root spans
+    parent: Option<InternalId>,
     records: Vec<Record>,
-    follows: Vec<SpanId>,
+    follows: Vec<InternalId>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct EventEntry {
+    pub source: SourceId,
     pub span: Option<InternalId>,
     pub event: Event,
 }
@@ -69,6 +584,167 @@ impl EventEntry {
     pub fn level(&self) -> Option<Level> {
         Level::from_i32(self.event.attributes.as_ref()?.metadata.as_ref()?.level)
     }
+
+    pub fn timestamp(&self) -> i64 {
+        self.event
+            .timestamp
+            .as_ref()
+            .map(|timestamp| timestamp.nano)
+            .unwrap_or(0)
+    }
+
+    /// Size of the message, accounted for by `Retention::max_bytes`
+    pub(crate) fn encoded_len(&self) -> usize {
+        self.event.encoded_len()
+    }
+
+    /// The source and parent as varint, `0` for no parent, followed by the length-delimited `Event`
+    pub(crate) fn encode(&self, buf: &mut Vec<u8>) {
+        encode_varint(self.source.0 as u64, buf);
+        encode_optional_id(self.span, buf);
+        self.event
+            .encode_length_delimited(buf)
+            .expect("BUG: Vec has insufficient capacity");
+    }
+
+    pub(crate) fn decode<B: Buf>(buf: &mut B) -> Result<EventEntry, DecodeError> {
+        let source = SourceId(decode_varint(buf)? as usize);
+        let span = decode_optional_id(buf)?;
+        let event = Event::decode_length_delimited(buf)?;
+        Ok(EventEntry {
+            source,
+            span,
+            event,
+        })
+    }
+}
+
+impl Span {
+    pub fn id(&self) -> InternalId {
+        self.id
+    }
+
+    pub fn source(&self) -> SourceId {
+        self.source
+    }
+
+    /// Whether the `NewSpan` is still missing, see `Anomalies`
+    pub fn is_placeholder(&self) -> bool {
+        self.placeholder
+    }
+
+    pub fn new_span(&self) -> &NewSpan {
+        &self.span
+    }
+
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.span.attributes.as_ref()?.metadata.as_ref()
+    }
+
+    pub fn parent(&self) -> Option<InternalId> {
+        self.parent
+    }
+
+    pub fn records(&self) -> &[Record] {
+        &self.records
+    }
+
+    /// Spans this one follows from
+    pub fn follows(&self) -> &[InternalId] {
+        &self.follows
+    }
+
+    fn timestamp(&self) -> i64 {
+        nano(&self.span.timestamp)
+    }
+
+    /// Size of the messages, accounted for by `Retention::max_bytes`
+    pub(crate) fn encoded_len(&self) -> usize {
+        self.span.encoded_len()
+            + self.records.iter().map(Message::encoded_len).sum::<usize>()
+            + self.follows.len() * EDGE_BYTES
+    }
+
+    /// The id, source, placeholder flag and parent as varint, the length-delimited `NewSpan`,
+    /// followed by the counted `Record`s and follows-from ids
+    pub(crate) fn encode(&self, buf: &mut Vec<u8>) {
+        encode_varint(self.id.0 as u64, buf);
+        encode_varint(self.source.0 as u64, buf);
+        encode_varint(self.placeholder as u64, buf);
+        encode_optional_id(self.parent, buf);
+        let expect = "BUG: Vec has insufficient capacity";
+        self.span.encode_length_delimited(buf).expect(expect);
+        encode_varint(self.records.len() as u64, buf);
+        for record in &self.records {
+            record.encode_length_delimited(buf).expect(expect);
+        }
+        encode_varint(self.follows.len() as u64, buf);
+        for follows in &self.follows {
+            encode_varint(follows.0 as u64, buf);
+        }
+    }
+
+    pub(crate) fn decode<B: Buf>(buf: &mut B) -> Result<Span, DecodeError> {
+        let id = InternalId(decode_varint(buf)? as usize);
+        let source = SourceId(decode_varint(buf)? as usize);
+        let placeholder = decode_varint(buf)? != 0;
+        let parent = decode_optional_id(buf)?;
+        let span = NewSpan::decode_length_delimited(&mut *buf)?;
+        let records = (0..decode_varint(buf)?)
+            .map(|_| Record::decode_length_delimited(&mut *buf))
+            .collect::<Result<_, _>>()?;
+        let follows = (0..decode_varint(buf)?)
+            .map(|_| decode_varint(buf).map(|id| InternalId(id as usize)))
+            .collect::<Result<_, _>>()?;
+        Ok(Span {
+            id,
+            source,
+            span,
+            placeholder,
+            parent,
+            records,
+            follows,
+        })
+    }
+}
+
+/// Orders events by timestamp, events with the same timestamp by their index
+///
+/// Events are stored in the order they arrived, see `Store::hot_events`.
+pub fn sort_timeline(events: &mut [(usize, EventEntry)]) {
+    events.sort_by_key(|(index, entry)| (entry.timestamp(), *index));
+}
+
+fn nano(timestamp: &Option<Timestamp>) -> i64 {
+    timestamp
+        .as_ref()
+        .map(|timestamp| timestamp.nano)
+        .unwrap_or(0)
+}
+
+/// Number of subscriber errors kept, see `Store::subscriber_errors`
+const MAX_SUBSCRIBER_ERRORS: usize = 16;
+
+/// Accounted size of a follows-from edge, it's stored as `InternalId`
+const EDGE_BYTES: usize = std::mem::size_of::<InternalId>();
+
+/// `0` for none, the id + 1 otherwise
+fn encode_optional_id(id: Option<InternalId>, buf: &mut Vec<u8>) {
+    encode_varint(id.map(|id| id.0 as u64 + 1).unwrap_or(0), buf);
+}
+
+fn decode_optional_id<B: Buf>(buf: &mut B) -> Result<Option<InternalId>, DecodeError> {
+    Ok(match decode_varint(buf)? {
+        0 => None,
+        id => Some(InternalId(id as usize - 1)),
+    })
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PanicEntry {
+    /// Spans entered by the panicking thread, innermost last
+    pub spans: Vec<InternalId>,
+    pub panic: Panic,
 }
 
 /// Convenience Wrapper around `Arc<Mutex<Store>>`
@@ -80,64 +756,1309 @@ impl StoreHandle {
         StoreHandle::default()
     }
 
-    /// Locks and updates the underlying `Store`
+    /// Locks and updates the underlying `Store`, with a message of the default source
     pub fn handle(&self, variant: Variant) {
+        self.0.lock().unwrap().handle(SourceId::default(), variant);
+    }
+
+    /// Indexes field `name` of all retained events, unless it's indexed already
+    ///
+    /// Spilled events are read one segment at a time, without holding the lock,
+    /// so it's meant to run off the UI thread. See `Store::start_index`.
+    pub fn index_field(&self, name: &str) -> io::Result<()> {
+        let build = match self.0.lock().unwrap().start_index(name) {
+            Some(build) => build,
+            None => return Ok(()),
+        };
+        let spilled = build.run();
+        self.0.lock().unwrap().finish_index(name, spilled)
+    }
+
+    /// Notifies about changes from now on, the first notification covers everything so far
+    pub fn watch(&self) -> Watcher {
+        let (watcher, pending) = Watcher::new();
+        self.0.lock().unwrap().watchers.push(pending);
+        watcher
+    }
+
+    /// Registers a connection, see `Store::add_source`
+    pub fn add_source(&self, name: &str) -> SourceId {
+        self.0.lock().unwrap().add_source(name)
+    }
+
+    /// Locks and updates the underlying `Store`, with a response received from `source`
+    ///
+    /// Responses tagged by a relay are attributed to the process they originate from.
+    pub fn handle_response(&self, source: SourceId, response: ListenResponse) {
         let mut store = self.0.lock().unwrap();
-        match variant {
-            Variant::NewSpan(span) => store.new_span(span),
-            Variant::Record(record) => store.record(record),
-            Variant::Follows(follows) => store.record_follows_from(follows),
-            Variant::Event(event) => store.event(event),
+        let source = match response.source.as_str() {
+            "" => source,
+            relayed => store.add_source(relayed),
+        };
+        match response.variant {
+            Some(variant) => store.handle(source, variant),
+            None => store.anomalies.malformed += 1,
         }
     }
 }
 
 impl Store {
-    fn new_span(&mut self, span: NewSpan) {
-        // Update id mapping for span, see `Store` documentation
-        self.id_map.insert(
-            span.span
-                .as_ref()
-                .expect("BUG: No id assined to NewSpan")
-                .id,
-            InternalId(self.id_counter),
-        );
+    fn handle(&mut self, source: SourceId, variant: Variant) {
+        match &variant {
+            Variant::Event(_) => {}
+            Variant::Panic(_) | Variant::Stats(_) | Variant::Process(_) => {
+                self.changes.status = true
+            }
+            _ => self.changes.spans = true,
+        }
+        match variant {
+            Variant::NewSpan(span) => self.new_span(source, span),
+            Variant::Record(record) => self.record(source, record),
+            Variant::Follows(follows) => self.record_follows_from(source, follows),
+            Variant::Event(event) => self.event(source, event),
+            Variant::Panic(panic) => self.panic(source, panic),
+            Variant::Stats(stats) => self.subscriber_stats(stats),
+            Variant::Process(sample) => self.process_sample(sample),
+            Variant::Close(close) => self.close_span(source, close),
+        }
+        self.notify();
+    }
+
+    /// Hands the changes of the current message to the watchers
+    fn notify(&mut self) {
+        if self.changes.is_empty() {
+            return;
+        }
+        let changes = mem::replace(&mut self.changes, Changes::default());
+        // Only the store refers to the changes of dropped watchers
+        self.watchers
+            .retain(|pending| Arc::strong_count(pending) > 1);
+        for pending in &self.watchers {
+            pending.notify(&changes);
+        }
+    }
+
+    fn span_index(&self, id: InternalId) -> Option<usize> {
+        self.spans.binary_search_by_key(&id, |span| span.id).ok()
+    }
+
+    /// The internal id of a subscriber id, unknown ones are mapped to a new placeholder
+    fn resolve(&mut self, source: SourceId, id: u64) -> InternalId {
+        if let Some(internal) = self.id_map.get(&(source, id)) {
+            return *internal;
+        }
+        self.anomalies.placeholders += 1;
+        let placeholder = NewSpan {
+            span: Some(SpanId { id }),
+            ..NewSpan::default()
+        };
+        let internal = self.push_span(source, placeholder, None);
+        self.spans.last_mut().unwrap().placeholder = true;
+        self.id_map.insert((source, id), internal);
+        internal
+    }
+
+    /// Spans which are still mapped are always in memory
+    fn mapped_span_mut(&mut self, source: SourceId, id: u64) -> &mut Span {
+        let internal = self.resolve(source, id);
+        let i = self
+            .span_index(internal)
+            .expect("BUG: Mapped span was spilled");
+        &mut self.spans[i]
+    }
 
+    fn push_span(
+        &mut self,
+        source: SourceId,
+        span: NewSpan,
+        parent: Option<InternalId>,
+    ) -> InternalId {
+        let id = InternalId(self.id_counter);
+        self.id_counter += 1;
+        self.bytes += span.encoded_len();
+        self.tree.insert(id, parent, nano(&span.timestamp));
+        if let Some(metadata) = span.attributes.as_ref().and_then(|a| a.metadata.as_ref()) {
+            self.tree.set_name(id, &metadata.name);
+        }
+        self.tree
+            .fields_mut(id)
+            .set(nano(&span.timestamp), &span.values);
+        self.latest_nano = self.latest_nano.max(nano(&span.timestamp));
         self.spans.push(Span {
-            id: InternalId(self.id_counter),
+            id,
+            source,
             span,
+            placeholder: false,
+            parent,
             records: vec![],
             follows: vec![],
         });
-        self.id_counter += 1;
+        if let Some(parent) = parent {
+            self.acquire_span(parent);
+        }
+        id
     }
 
-    fn record_follows_from(&mut self, follows: RecordFollowsFrom) {
-        let span = self.id_map[&follows
-            .span
+    /// The subscriber resolves the parent, `Attributes` are a fallback for explicit ones
+    fn resolve_parent(&mut self, source: SourceId, span: &NewSpan) -> Option<InternalId> {
+        let attributes = span.attributes.as_ref();
+        if attributes.map_or(false, |attributes| attributes.is_root) {
+            return None;
+        }
+        let parent = span
+            .parent
             .as_ref()
-            .expect("BUG: No id set on follows.span")
-            .id];
-        self.spans[span.0]
-            .follows
-            .push(follows.follows.expect("BUG: No id set on follows.follows"));
+            .or_else(|| attributes?.parent.as_ref())?;
+        Some(self.resolve(source, parent.id))
     }
 
-    fn record(&mut self, record: Record) {
+    fn new_span(&mut self, source: SourceId, span: NewSpan) {
+        let id = match &span.span {
+            Some(id) => id.id,
+            None => {
+                self.anomalies.malformed += 1;
+                return;
+            }
+        };
+        if let Some(internal) = self.id_map.get(&(source, id)).cloned() {
+            if self.span(internal).map_or(false, Span::is_placeholder) {
+                return self.backfill(source, internal, span);
+            }
+        }
+
+        // Update id mapping for span, see `Store` documentation
+        let parent = self.resolve_parent(source, &span);
+        let internal = self.push_span(source, span, parent);
+        let replaced = self.id_map.insert((source, id), internal);
+
+        if let Some(replaced) = replaced {
+            // Closed before the id was reused, after its last activity
+            let lifecycle = self.tree.lifecycle_mut(replaced);
+            lifecycle.closed_at = Some(lifecycle.last_active.unwrap_or(lifecycle.created_at));
+            self.close(replaced);
+        }
+        self.evict();
+    }
+
+    fn close_span(&mut self, source: SourceId, close: SpanClose) {
         self.updated = true;
-        let span = self.id_map[&record
-            .span
+        let id = match &close.span {
+            Some(span) => span.id,
+            None => {
+                self.anomalies.malformed += 1;
+                return;
+            }
+        };
+        let internal = self.resolve(source, id);
+        let closed_at = nano(&close.timestamp);
+        self.latest_nano = self.latest_nano.max(closed_at);
+        let lifecycle = self.tree.lifecycle_mut(internal);
+        lifecycle.closed_at = Some(closed_at);
+        lifecycle.first_entered = close.first_entered.map(|timestamp| timestamp.nano);
+        lifecycle.busy_nanos = Some(close.busy_nanos);
+
+        // The subscriber might reuse the id from now on
+        self.id_map.remove(&(source, id));
+        self.close(internal);
+        self.evict();
+    }
+
+    /// Marks a span which was unmapped as closed, it won't receive updates anymore
+    fn close(&mut self, id: InternalId) {
+        let span = &self.spans[self.span_index(id).expect("BUG: Mapped span was spilled")];
+        self.evictable
+            .insert(id, (span.timestamp(), span.encoded_len()));
+        self.retain_spans = true;
+        self.closed.push(id);
+        self.spill_spans();
+    }
+
+    /// Fills in a placeholder, see `Anomalies`
+    fn backfill(&mut self, source: SourceId, internal: InternalId, span: NewSpan) {
+        self.anomalies.backfilled += 1;
+        let mut parent = self.resolve_parent(source, &span);
+        // Garbled parents could link the span below itself
+        if parent.map_or(false, |parent| {
+            parent == internal || self.ancestors(parent).any(|id| id == internal)
+        }) {
+            self.anomalies.malformed += 1;
+            parent = None;
+        }
+        if let Some(parent) = parent {
+            self.tree.set_parent(internal, Some(parent));
+            self.acquire_span(parent);
+        }
+        self.tree.lifecycle_mut(internal).created_at = nano(&span.timestamp);
+        if let Some(metadata) = span.attributes.as_ref().and_then(|a| a.metadata.as_ref()) {
+            self.tree.set_name(internal, &metadata.name);
+        }
+        self.tree
+            .fields_mut(internal)
+            .set(nano(&span.timestamp), &span.values);
+
+        let i = self
+            .span_index(internal)
+            .expect("BUG: Mapped span was spilled");
+        let placeholder = &mut self.spans[i];
+        let (added, removed) = (span.encoded_len(), placeholder.span.encoded_len());
+        placeholder.span = span;
+        placeholder.placeholder = false;
+        placeholder.parent = parent;
+        self.bytes = self.bytes + added - removed;
+    }
+
+    fn record_follows_from(&mut self, source: SourceId, follows: RecordFollowsFrom) {
+        let (span, follows) = match (follows.span, follows.follows) {
+            (Some(span), Some(follows)) => (span.id, follows.id),
+            _ => {
+                self.anomalies.malformed += 1;
+                return;
+            }
+        };
+        let follows = self.resolve(source, follows);
+        self.bytes += EDGE_BYTES;
+        let span = self.mapped_span_mut(source, span);
+        span.follows.push(follows);
+        let span = span.id;
+        self.tree.add_follows(span, follows);
+        self.acquire_span(follows);
+    }
+
+    fn record(&mut self, source: SourceId, record: Record) {
+        self.updated = true;
+        let span = match &record.span {
+            Some(span) => span.id,
+            None => {
+                self.anomalies.malformed += 1;
+                return;
+            }
+        };
+        self.bytes += record.encoded_len();
+        // Older subscribers don't timestamp records
+        let timestamp = record
+            .timestamp
             .as_ref()
-            .expect("BUG: No id set on record.span")
-            .id];
-        self.spans[span.0].records.push(record);
+            .map_or(self.latest_nano, |timestamp| timestamp.nano);
+        let internal = self.resolve(source, span);
+        self.tree
+            .fields_mut(internal)
+            .set(timestamp, &record.values);
+        self.mapped_span_mut(source, span).records.push(record);
     }
 
-    fn event(&mut self, event: Event) {
+    fn event(&mut self, source: SourceId, event: Event) {
         self.updated = true;
-        self.events.push(EventEntry {
-            span: event.span.as_ref().map(|span| self.id_map[&span.id]),
+        let entry = EventEntry {
+            source,
+            span: event
+                .span
+                .as_ref()
+                .map(|span| self.resolve(source, span.id)),
             event,
+        };
+        let timestamp = entry.timestamp();
+        self.latest_nano = self.latest_nano.max(timestamp);
+        self.bytes += entry.encoded_len();
+        let mut entered = self.entered.remove(&source).unwrap_or_default();
+        entered.clear();
+        if let Some(span) = entry.span {
+            self.acquire_span(span);
+            // The event implies the span and its ancestors were entered
+            let mut next = Some(span);
+            while let Some(id) = next {
+                entered.push(id);
+                next = self.tree.parent(id);
+            }
+            for &id in &entered {
+                self.tree.lifecycle_mut(id).activity(timestamp);
+            }
+        }
+        self.entered.insert(source, entered);
+        let position = self.event_count();
+        for (name, index) in self.indexes.iter_mut().chain(&mut self.building) {
+            index.insert(position, entry.event.any_by_name(name));
+        }
+        self.events.push_back(entry);
+        self.changes.merge(&Changes {
+            events_from: Some(position),
+            ..Changes::default()
         });
+        self.spill_events();
+        self.evict();
+    }
+
+    /// Spills the oldest events, once there are enough for a segment
+    fn spill_events(&mut self) {
+        let segments = match &mut self.segments {
+            Some(segments) if segments.enabled() => segments,
+            _ => return,
+        };
+        let config = segments.config();
+        let len = config.segment_events;
+        if self.events.len() < config.hot_events + len {
+            return;
+        }
+        let batch: Vec<EventEntry> = self.events.drain(..len).collect();
+        match segments.spill_events(self.hot_start, &batch) {
+            Ok(()) => self.hot_start += len,
+            Err(_) => {
+                // Keep everything in memory from now on
+                for entry in batch.into_iter().rev() {
+                    self.events.push_front(entry);
+                }
+                segments.disable();
+            }
+        }
+    }
+
+    /// Spills closed spans, once there are enough of them
+    fn spill_spans(&mut self) {
+        let segments = match &mut self.segments {
+            Some(segments) if segments.enabled() => segments,
+            _ => return,
+        };
+        if self.closed.len() < segments.config().closed_spans {
+            return;
+        }
+        self.closed.sort();
+        let closed = &self.closed;
+        let (spilled, hot): (Vec<Span>, Vec<Span>) = self
+            .spans
+            .drain(..)
+            .partition(|span| closed.binary_search(&span.id).is_ok());
+        self.spans = hot;
+        match segments.spill_spans(&spilled) {
+            Ok(()) => self.closed.clear(),
+            Err(_) => {
+                // Keep everything in memory from now on
+                self.spans.extend(spilled);
+                self.spans.sort_by_key(|span| span.id);
+                segments.disable();
+            }
+        }
+    }
+
+    fn panic(&mut self, source: SourceId, panic: Panic) {
+        self.updated = true;
+        let spans = panic
+            .spans
+            .iter()
+            .map(|span| self.resolve(source, span.id))
+            .collect::<Vec<_>>();
+        // Panics are never evicted, neither are their spans
+        for span in &spans {
+            self.acquire_span(*span);
+        }
+        self.panics.push(PanicEntry { spans, panic });
+    }
+
+    /// Evicts whatever exceeds the `Retention`
+    fn evict(&mut self) {
+        let retention = self.retention;
+        if !retention.is_limited() {
+            return;
+        }
+        let cutoff = retention
+            .max_age
+            .map(|age| self.latest_nano.saturating_sub(age.as_nanos() as i64));
+        loop {
+            let over = retention
+                .max_events
+                .map_or(false, |max| self.retained_events() > max)
+                || retention.max_bytes.map_or(false, |max| self.bytes > max);
+            let expired = match (cutoff, self.next_evicted_nano()) {
+                (Some(cutoff), Some(nano)) => nano < cutoff,
+                _ => false,
+            };
+            if !(over || expired) || !self.evict_events() {
+                break;
+            }
+        }
+        if self.retain_spans {
+            self.retain_spans = false;
+            self.evict_spans(cutoff);
+        }
+        let first_event = self.first_event();
+        for index in self.indexes.values_mut().chain(self.building.values_mut()) {
+            index.evicted(first_event);
+        }
+    }
+
+    /// Latest timestamp among the events `evict_events` would remove
+    fn next_evicted_nano(&self) -> Option<i64> {
+        match self.segments.as_ref().and_then(Segments::oldest_nanos) {
+            Some((_, max_nano)) => Some(max_nano),
+            None => self.events.front().map(EventEntry::timestamp),
+        }
+    }
+
+    /// Evicts the oldest spilled segment, or the oldest event in memory if there is none
+    fn evict_events(&mut self) -> bool {
+        if let Some(evicted) = self.segments.as_mut().and_then(Segments::evict_events) {
+            self.bytes -= evicted.bytes;
+            for (span, count) in evicted.spans {
+                self.release_span(span, count);
+            }
+        } else if let Some(entry) = self.events.pop_front() {
+            self.hot_start += 1;
+            self.bytes -= entry.encoded_len();
+            if let Some(span) = entry.span {
+                self.release_span(span, 1);
+            }
+        } else {
+            return false;
+        }
+        self.changes.evicted = true;
+        self.retain_spans = true;
+        true
+    }
+
+    fn acquire_span(&mut self, span: InternalId) {
+        *self.span_refs.entry(span).or_insert(0) += 1;
+    }
+
+    fn release_span(&mut self, span: InternalId, count: usize) {
+        let refs = self
+            .span_refs
+            .get_mut(&span)
+            .expect("BUG: Span references out of sync");
+        *refs -= count;
+        if *refs == 0 {
+            self.span_refs.remove(&span);
+        }
+    }
+
+    /// Evicts closed spans nothing refers to anymore, oldest first
+    fn evict_spans(&mut self, cutoff: Option<i64>) {
+        let retention = self.retention;
+        let mut count = self.span_count();
+        let mut bytes = self.bytes;
+        let mut evicted = Vec::new();
+        for (&id, &(nano, size)) in &self.evictable {
+            let over = retention.max_spans.map_or(false, |max| count > max)
+                || retention.max_bytes.map_or(false, |max| bytes > max);
+            let expired = cutoff.map_or(false, |cutoff| nano < cutoff);
+            if !(over || expired) {
+                break;
+            }
+            if self.span_refs.contains_key(&id) {
+                continue;
+            }
+            evicted.push(id);
+            count -= 1;
+            bytes -= size;
+        }
+        if evicted.is_empty() {
+            return;
+        }
+
+        self.bytes = bytes;
+        for id in &evicted {
+            self.evictable.remove(id);
+            if let Some(segments) = &mut self.segments {
+                segments.evict_span(*id);
+            }
+            for referred in self.tree.remove(*id) {
+                self.release_span(referred, 1);
+            }
+        }
+        // Parents might not be referred to anymore
+        self.retain_spans = true;
+        // Ordered by id, as taken from `evictable`
+        self.spans
+            .retain(|span| evicted.binary_search(&span.id).is_err());
+        self.closed.retain(|id| evicted.binary_search(id).is_err());
+    }
+
+    fn subscriber_stats(&mut self, mut stats: SubscriberStats) {
+        self.updated = true;
+        self.subscriber_errors
+            .extend(mem::replace(&mut stats.errors, Vec::new()));
+        let excess = self
+            .subscriber_errors
+            .len()
+            .saturating_sub(MAX_SUBSCRIBER_ERRORS);
+        self.subscriber_errors.drain(..excess);
+        self.stats = Some(stats);
+    }
+
+    fn process_sample(&mut self, sample: ProcessSample) {
+        self.updated = true;
+        self.process_samples.push(sample);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::storage::FieldChange;
+
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    /// Number of spill directories created by this test process
+    static SPILL_DIRS: AtomicUsize = AtomicUsize::new(0);
+
+    /// A spill directory of its own, removed on drop, even if the test fails
+    struct SpillDir(PathBuf);
+
+    impl Drop for SpillDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn new_span(id: u64) -> Variant {
+        Variant::NewSpan(NewSpan {
+            span: Some(SpanId { id }),
+            ..NewSpan::default()
+        })
+    }
+
+    fn event(span: u64, nano: i64) -> Variant {
+        Variant::Event(Event {
+            span: Some(SpanId { id: span }),
+            timestamp: Some(Timestamp { nano }),
+            ..Event::default()
+        })
+    }
+
+    /// Tests and proptest cases run concurrently, each one spills to a unique directory
+    fn spill_dir() -> SpillDir {
+        SpillDir(env::temp_dir().join(format!(
+            "tracing-console-test-{}-{}",
+            process::id(),
+            SPILL_DIRS.fetch_add(1, Ordering::SeqCst)
+        )))
+    }
+
+    fn spill_config(dir: &SpillDir) -> SpillConfig {
+        SpillConfig {
+            dir: dir.0.clone(),
+            hot_events: 10,
+            segment_events: 5,
+            closed_spans: 2,
+        }
+    }
+
+    fn spilling_store() -> (StoreHandle, SpillDir) {
+        let dir = spill_dir();
+        let store = StoreHandle::new();
+        store
+            .0
+            .lock()
+            .unwrap()
+            .spill_to(spill_config(&dir))
+            .unwrap();
+        (store, dir)
+    }
+
+    #[test]
+    fn spill_events() {
+        let (handle, _dir) = spilling_store();
+        handle.handle(new_span(1));
+        for nano in 0..32 {
+            handle.handle(event(1, nano));
+        }
+
+        let store = handle.0.lock().unwrap();
+        assert_eq!(store.event_count(), 32);
+        assert_eq!(store.cold_events(), 20);
+        assert_eq!(store.hot_events().len(), 12);
+
+        let events = store.load_events(0..32).unwrap();
+        let nanos: Vec<i64> = events.iter().map(EventEntry::timestamp).collect();
+        assert_eq!(nanos, (0..32).collect::<Vec<_>>());
+        assert_eq!(store.load_events(3..7).unwrap(), events[3..7].to_vec());
+        assert_eq!(store.load_events(18..23).unwrap(), events[18..23].to_vec());
+        assert_eq!(events[0].span, Some(store.spans()[0].id()));
+
+        assert_eq!(store.event_index_at(7).unwrap(), 7);
+        assert_eq!(store.event_index_at(25).unwrap(), 25);
+        assert_eq!(store.event_index_at(100).unwrap(), 32);
+    }
+
+    #[test]
+    fn spill_closed_spans() {
+        let (handle, _dir) = spilling_store();
+        handle.handle(new_span(1));
+        handle.handle(Variant::Record(Record {
+            span: Some(SpanId { id: 1 }),
+            ..Record::default()
+        }));
+        handle.handle(event(1, 0));
+        // Reusing the subscriber id closes the previous span
+        handle.handle(new_span(1));
+        handle.handle(new_span(1));
+
+        let store = handle.0.lock().unwrap();
+        let closed = store.hot_events()[0].span.unwrap();
+        assert!(store.span(closed).is_none());
+        let span = store.load_span(closed).unwrap().unwrap();
+        assert_eq!(span.id(), closed);
+        assert_eq!(span.records.len(), 1);
+        // The current span stays in memory
+        assert_eq!(store.spans().len(), 1);
+    }
+
+    #[test]
+    fn spill_keeps_foreign_files() {
+        let dir = spill_dir();
+        fs::create_dir(&dir.0).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&dir.0, fs::Permissions::from_mode(0o700)).unwrap();
+        }
+        fs::write(dir.0.join("notes.txt"), "keep").unwrap();
+
+        let handle = StoreHandle::new();
+        handle
+            .0
+            .lock()
+            .unwrap()
+            .spill_to(spill_config(&dir))
+            .unwrap();
+        for nano in 0..20 {
+            handle.handle(event(1, nano));
+        }
+        assert!(dir.0.join("events-0.seg").exists());
+        drop(handle);
+        assert!(!dir.0.join("events-0.seg").exists());
+        assert!(dir.0.join("notes.txt").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn spill_rejects_shared_directory() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = spill_dir();
+        fs::create_dir(&dir.0).unwrap();
+        fs::set_permissions(&dir.0, fs::Permissions::from_mode(0o755)).unwrap();
+        let error = Store::new().spill_to(spill_config(&dir)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn retain_events_and_spans() {
+        let handle = StoreHandle::new();
+        handle.0.lock().unwrap().retain(Retention {
+            max_events: Some(4),
+            max_spans: Some(1),
+            ..Retention::default()
+        });
+        handle.handle(new_span(1));
+        handle.handle(event(1, 0));
+        handle.handle(event(1, 1));
+        // The closed span is kept, as long as its events are
+        handle.handle(new_span(1));
+        assert_eq!(handle.0.lock().unwrap().span_count(), 2);
+        for nano in 2..6 {
+            handle.handle(event(1, nano));
+        }
+
+        let store = handle.0.lock().unwrap();
+        assert_eq!(store.event_count(), 6);
+        assert_eq!(store.first_event(), 2);
+        let events = store.load_events(0..6).unwrap();
+        let nanos: Vec<i64> = events.iter().map(EventEntry::timestamp).collect();
+        assert_eq!(nanos, vec![2, 3, 4, 5]);
+        assert_eq!(store.span_count(), 1);
+        assert!(events
+            .iter()
+            .all(|entry| store.span(entry.span.unwrap()).is_some()));
+    }
+
+    #[test]
+    fn retain_spilled_events() {
+        let (handle, dir) = spilling_store();
+        handle.0.lock().unwrap().retain(Retention {
+            max_age: Some(Duration::from_nanos(3)),
+            ..Retention::default()
+        });
+        handle.handle(new_span(1));
+        for i in 0..32 {
+            handle.handle(event(1, i / 4));
+        }
+
+        let store = handle.0.lock().unwrap();
+        // Spilled events are evicted in whole segments
+        assert_eq!(store.first_event(), 16);
+        assert_eq!(store.time_window(), Some((4, 7)));
+        let nanos: Vec<i64> = store
+            .load_events(0..32)
+            .unwrap()
+            .iter()
+            .map(EventEntry::timestamp)
+            .collect();
+        assert_eq!(nanos, (16..32).map(|i| i / 4).collect::<Vec<_>>());
+        assert!(!dir.0.join("events-0.seg").exists());
+        assert!(dir.0.join("events-16.seg").exists());
+    }
+
+    #[test]
+    fn backfill_placeholder() {
+        let handle = StoreHandle::new();
+        handle.handle(event(1, 0));
+        handle.handle(Variant::Record(Record {
+            span: Some(SpanId { id: 1 }),
+            ..Record::default()
+        }));
+        handle.handle(Variant::NewSpan(NewSpan {
+            span: Some(SpanId { id: 1 }),
+            timestamp: Some(Timestamp { nano: 7 }),
+            ..NewSpan::default()
+        }));
+        handle.handle(Variant::Record(Record::default()));
+
+        let store = handle.0.lock().unwrap();
+        let span = store.span(store.hot_events()[0].span.unwrap()).unwrap();
+        assert!(!span.is_placeholder());
+        assert_eq!(span.timestamp(), 7);
+        assert_eq!(span.records.len(), 1);
+        assert_eq!(store.span_count(), 1);
+        assert_eq!(
+            store.anomalies(),
+            &Anomalies {
+                malformed: 1,
+                placeholders: 1,
+                backfilled: 1,
+            }
+        );
+    }
+
+    fn child_span(id: u64, parent: u64) -> Variant {
+        Variant::NewSpan(NewSpan {
+            span: Some(SpanId { id }),
+            parent: Some(SpanId { id: parent }),
+            ..NewSpan::default()
+        })
+    }
+
+    #[test]
+    fn span_tree() {
+        let handle = StoreHandle::new();
+        handle.handle(new_span(1));
+        handle.handle(child_span(2, 1));
+        // Only an explicit parent, as sent by older subscribers
+        handle.handle(Variant::NewSpan(NewSpan {
+            span: Some(SpanId { id: 3 }),
+            attributes: Some(Attributes {
+                parent: Some(SpanId { id: 2 }),
+                ..Attributes::default()
+            }),
+            ..NewSpan::default()
+        }));
+        handle.handle(Variant::NewSpan(NewSpan {
+            span: Some(SpanId { id: 4 }),
+            parent: Some(SpanId { id: 1 }),
+            attributes: Some(Attributes {
+                is_root: true,
+                ..Attributes::default()
+            }),
+            ..NewSpan::default()
+        }));
+        handle.handle(Variant::Follows(RecordFollowsFrom {
+            span: Some(SpanId { id: 4 }),
+            follows: Some(SpanId { id: 3 }),
+        }));
+        handle.handle(event(2, 0));
+        handle.handle(event(3, 1));
+        handle.handle(event(2, 2));
+        // The parent arrives late
+        handle.handle(child_span(5, 6));
+        handle.handle(new_span(6));
+
+        let store = handle.0.lock().unwrap();
+        let ids: Vec<InternalId> = store.spans().iter().map(Span::id).collect();
+        let (one, two, three, four) = (ids[0], ids[1], ids[2], ids[3]);
+        assert_eq!(store.children(one), &[two]);
+        assert_eq!(store.ancestors(three).collect::<Vec<_>>(), vec![two, one]);
+        assert_eq!(store.descendants(one).collect::<Vec<_>>(), vec![two, three]);
+        assert_eq!(store.parent(four), None);
+        assert_eq!(store.follows(four), &[three]);
+        let events: Vec<usize> = store
+            .events_in(two)
+            .unwrap()
+            .into_iter()
+            .map(|(i, _)| i)
+            .collect();
+        assert_eq!(events, vec![0, 2]);
+        // The placeholder was created first
+        let (six, five) = (ids[4], ids[5]);
+        assert_eq!(store.children(six), &[five]);
+        assert!(!store.span(six).unwrap().is_placeholder());
+    }
+
+    fn named_span(id: u64, name: &str, nano: i64) -> Variant {
+        Variant::NewSpan(NewSpan {
+            span: Some(SpanId { id }),
+            timestamp: Some(Timestamp { nano }),
+            attributes: Some(Attributes {
+                metadata: Some(Metadata {
+                    name: name.to_string(),
+                    ..Metadata::default()
+                }),
+                ..Attributes::default()
+            }),
+            ..NewSpan::default()
+        })
+    }
+
+    #[test]
+    fn span_lifecycle() {
+        let handle = StoreHandle::new();
+        handle.handle(named_span(1, "request", 0));
+        handle.handle(named_span(2, "request", 10));
+        handle.handle(child_span(3, 2));
+        handle.handle(event(1, 20));
+        handle.handle(event(3, 30));
+        handle.handle(Variant::Close(SpanClose {
+            span: Some(SpanId { id: 1 }),
+            timestamp: Some(Timestamp { nano: 50 }),
+            first_entered: Some(Timestamp { nano: 10 }),
+            busy_nanos: 40,
+        }));
+        // Reusing the id closes the span, after its last activity
+        handle.handle(named_span(2, "request", 100));
+
+        let store = handle.0.lock().unwrap();
+        let ids: Vec<InternalId> = store.spans().iter().map(Span::id).collect();
+        let (reported, inferred, child, reused) = (ids[0], ids[1], ids[2], ids[3]);
+        assert_eq!(
+            store.lifecycle(reported),
+            Some(&Lifecycle {
+                created_at: 0,
+                first_entered: Some(10),
+                last_active: Some(20),
+                closed_at: Some(50),
+                busy_nanos: Some(40),
+            })
+        );
+        let lifecycle = store.lifecycle(inferred).unwrap();
+        assert_eq!(lifecycle.closed_at, Some(30));
+        assert_eq!(lifecycle.busy(), Some(Duration::from_nanos(0)));
+
+        assert_eq!(store.state(reported), Some(SpanState::Closed));
+        assert_eq!(store.state(child), Some(SpanState::Entered));
+        assert_eq!(store.state(reused), Some(SpanState::Open));
+        assert_eq!(
+            store.longest_spans("request", 2),
+            vec![
+                (reported, Duration::from_nanos(50)),
+                (inferred, Duration::from_nanos(20)),
+            ]
+        );
+        assert_eq!(store.open_spans(Duration::from_nanos(50)), vec![child]);
+    }
+
+    fn str_value(name: &str, value: &str) -> Value {
+        Value {
+            field: Some(Field {
+                name: name.to_string(),
+            }),
+            value: Some(value::Value::Str(value.to_string())),
+        }
+    }
+
+    #[test]
+    fn span_fields() {
+        let handle = StoreHandle::new();
+        // The record arrives before the span it belongs to
+        handle.handle(Variant::Record(Record {
+            span: Some(SpanId { id: 1 }),
+            values: vec![str_value("status", "done")],
+            timestamp: Some(Timestamp { nano: 20 }),
+            ..Record::default()
+        }));
+        handle.handle(Variant::NewSpan(NewSpan {
+            span: Some(SpanId { id: 1 }),
+            timestamp: Some(Timestamp { nano: 10 }),
+            values: vec![str_value("user", "ferris"), str_value("status", "pending")],
+            ..NewSpan::default()
+        }));
+
+        let store = handle.0.lock().unwrap();
+        let span = &store.spans()[0];
+        assert_eq!(span.records().len(), 1);
+        let fields = store.fields(span.id()).unwrap();
+        let current: Vec<_> = fields
+            .iter()
+            .map(|(name, value)| (name, value.to_string()))
+            .collect();
+        assert_eq!(
+            current,
+            vec![
+                ("status", "done".to_string()),
+                ("user", "ferris".to_string())
+            ]
+        );
+        assert_eq!(
+            fields.history("status"),
+            &[
+                FieldChange {
+                    nano: 10,
+                    value: value::Value::Str("pending".to_string()),
+                },
+                FieldChange {
+                    nano: 20,
+                    value: value::Value::Str("done".to_string()),
+                },
+            ][..]
+        );
+    }
+
+    #[test]
+    fn multiple_sources() {
+        let handle = StoreHandle::new();
+        let client = handle.add_source("http://[::1]:50051");
+        let server = handle.add_source("http://[::1]:50052");
+        assert_eq!(handle.add_source("http://[::1]:50051"), client);
+        let response = |variant| ListenResponse {
+            variant: Some(variant),
+            source: String::new(),
+        };
+
+        // Both processes use the same span id
+        handle.handle_response(client, response(new_span(1)));
+        handle.handle_response(server, response(new_span(1)));
+        handle.handle_response(client, response(event(1, 10)));
+        handle.handle_response(server, response(event(1, 30)));
+        // Arrives late
+        handle.handle_response(server, response(event(1, 20)));
+        // Tagged by a relay
+        handle.handle_response(
+            server,
+            ListenResponse {
+                variant: Some(new_span(1)),
+                source: "push://[::1]:40000".to_string(),
+            },
+        );
+
+        let store = handle.0.lock().unwrap();
+        let sources: Vec<_> = store.sources().map(|(_, name)| name).collect();
+        assert_eq!(
+            sources,
+            vec![
+                "http://[::1]:50051",
+                "http://[::1]:50052",
+                "push://[::1]:40000"
+            ]
+        );
+        let spans: Vec<_> = store.spans().iter().map(Span::source).collect();
+        assert_eq!(spans[..2], [client, server]);
+        assert_eq!(store.source_name(spans[2]), "push://[::1]:40000");
+
+        let events: Vec<_> = store
+            .hot_events()
+            .iter()
+            .map(|entry| (entry.source, entry.timestamp()))
+            .collect();
+        assert_eq!(events, vec![(client, 10), (server, 30), (server, 20)]);
+        let mut timeline: Vec<_> = store.hot_events().iter().cloned().enumerate().collect();
+        sort_timeline(&mut timeline);
+        let positions: Vec<_> = timeline.iter().map(|(i, _)| *i).collect();
+        assert_eq!(positions, vec![0, 2, 1]);
+        assert_eq!(store.hot_events()[0].span, Some(store.spans()[0].id()));
+        assert_eq!(store.hot_events()[1].span, Some(store.spans()[1].id()));
+    }
+
+    #[test]
+    fn watch_changes() {
+        let handle = StoreHandle::new();
+        handle.handle(new_span(1));
+        let watcher = handle.watch();
+        assert_eq!(watcher.take(), Changes::all());
+        assert!(watcher.take().is_empty());
+
+        handle.handle(event(1, 20));
+        handle.handle(event(1, 30));
+        // Arrives late, merged into one notification
+        handle.handle(event(1, 10));
+        assert_eq!(
+            watcher.wait(),
+            Changes {
+                events_from: Some(0),
+                ..Changes::default()
+            }
+        );
+
+        handle.handle(event(1, 40));
+        handle.handle(Variant::Record(Record {
+            span: Some(SpanId { id: 1 }),
+            ..Record::default()
+        }));
+        assert_eq!(
+            watcher.wait_timeout(Duration::from_secs(1)),
+            Changes {
+                events_from: Some(3),
+                spans: true,
+                ..Changes::default()
+            }
+        );
+        assert!(watcher.wait_timeout(Duration::from_millis(10)).is_empty());
+
+        drop(watcher);
+        handle.handle(event(1, 50));
+        assert!(handle.0.lock().unwrap().watchers.is_empty());
+    }
+
+    #[test]
+    fn field_index() {
+        let (handle, _dir) = spilling_store();
+        let conn_event = |nano: i64| {
+            Variant::Event(Event {
+                timestamp: Some(Timestamp { nano }),
+                values: vec![str_value("conn_id", &(nano % 3).to_string())],
+                ..Event::default()
+            })
+        };
+        for nano in (0..40).step_by(2) {
+            handle.handle(conn_event(nano));
+        }
+        // Covers the spilled events as well
+        handle.index_field("conn_id").unwrap();
+        for nano in (40..60).step_by(2) {
+            handle.handle(conn_event(nano));
+        }
+        // Arrives late, but is appended all the same
+        handle.handle(conn_event(55));
+
+        let store = handle.0.lock().unwrap();
+        assert!(store.cold_events() > 0);
+        let events = store.load_events(0..store.event_count()).unwrap();
+        let scanned = |value: &str| -> Vec<usize> {
+            (0..events.len())
+                .filter(|&i| events[i].event.any_by_name("conn_id") == Some(value.to_string()))
+                .collect()
+        };
+        let index = store.field_index("conn_id").unwrap();
+        assert_eq!(index.len(), 3);
+        assert_eq!(index.equal("1", 0..events.len()), &scanned("1")[..]);
+        assert_eq!(index.equal("1", 10..20), &scanned("1")[3..6]);
+        assert!(index.equal("3", 0..events.len()).is_empty());
+        let mut either = scanned("0");
+        either.extend(scanned("2"));
+        either.sort();
+        assert_eq!(
+            index.matching(|value| value != "1", 0..events.len()),
+            either
+        );
+
+        // Only the candidates are loaded
+        let candidates = store.load_events_at(&either).unwrap();
+        let positions: Vec<usize> = candidates.iter().map(|(i, _)| *i).collect();
+        assert_eq!(positions, either);
+        assert!(candidates.iter().all(|(i, entry)| *entry == events[*i]));
+    }
+
+    #[test]
+    fn field_index_built_during_ingest() {
+        let (handle, _dir) = spilling_store();
+        let conn_event = |nano: i64| {
+            Variant::Event(Event {
+                timestamp: Some(Timestamp { nano }),
+                values: vec![str_value("conn_id", &(nano % 3).to_string())],
+                ..Event::default()
+            })
+        };
+        for nano in 0..20 {
+            handle.handle(conn_event(nano));
+        }
+        let build = handle.0.lock().unwrap().start_index("conn_id").unwrap();
+        // Spilled and late events, while the spilled ones are read
+        for nano in 20..40 {
+            handle.handle(conn_event(nano));
+        }
+        handle.handle(conn_event(35));
+        let spilled = build.run();
+        let mut store = handle.0.lock().unwrap();
+        assert!(store.field_index("conn_id").is_none());
+        store.finish_index("conn_id", spilled).unwrap();
+
+        let events = store.load_events(0..store.event_count()).unwrap();
+        let index = store.field_index("conn_id").unwrap();
+        for value in &["0", "1", "2"] {
+            let scanned: Vec<usize> = (0..events.len())
+                .filter(|&i| events[i].event.any_by_name("conn_id") == Some(value.to_string()))
+                .collect();
+            assert_eq!(index.equal(value, 0..events.len()), &scanned[..]);
+        }
+    }
+
+    #[test]
+    fn field_index_interleaved_sources() {
+        let (handle, _dir) = spilling_store();
+        let client = handle.add_source("http://[::1]:50051");
+        let server = handle.add_source("http://[::1]:50052");
+        let conn_event = |nano: i64| ListenResponse {
+            variant: Some(Variant::Event(Event {
+                timestamp: Some(Timestamp { nano }),
+                values: vec![str_value("conn_id", &(nano % 3).to_string())],
+                ..Event::default()
+            })),
+            source: String::new(),
+        };
+        handle.index_field("conn_id").unwrap();
+        // The server lags behind, each of its events is older than the client's before
+        for nano in 0..40 {
+            handle.handle_response(client, conn_event(2 * nano + 10));
+            handle.handle_response(server, conn_event(2 * nano + 1));
+        }
+        let before = handle.0.lock().unwrap().load_events(0..80).unwrap();
+        handle.handle_response(server, conn_event(0));
+
+        let store = handle.0.lock().unwrap();
+        assert!(store.cold_events() > 0);
+        let events = store.load_events(0..store.event_count()).unwrap();
+        // Late events don't move the ones before
+        assert_eq!(events[..80], before[..]);
+        let index = store.field_index("conn_id").unwrap();
+        for value in &["0", "1", "2"] {
+            let scanned: Vec<usize> = (0..events.len())
+                .filter(|&i| events[i].event.any_by_name("conn_id") == Some(value.to_string()))
+                .collect();
+            assert_eq!(index.equal(value, 0..events.len()), &scanned[..]);
+        }
+
+        let mut timeline = store.load_events_at(index.equal("0", 0..81)).unwrap();
+        sort_timeline(&mut timeline);
+        let nanos: Vec<i64> = timeline
+            .iter()
+            .map(|(_, entry)| entry.timestamp())
+            .collect();
+        let mut sorted = nanos.clone();
+        sorted.sort();
+        assert_eq!(nanos, sorted);
+        assert_eq!(timeline[0].0, 80);
+    }
+
+    #[test]
+    fn field_index_failure() {
+        let (handle, _dir) = spilling_store();
+        assert!(handle.0.lock().unwrap().start_index("conn_id").is_some());
+        let error = io::Error::new(io::ErrorKind::InvalidData, "corrupt segment");
+        let mut store = handle.0.lock().unwrap();
+        assert!(store.finish_index("conn_id", Err(error)).is_err());
+        assert!(store.field_index("conn_id").is_none());
+        assert!(store.index_error().unwrap().contains("corrupt segment"));
+    }
+
+    fn span_id() -> impl Strategy<Value = Option<SpanId>> {
+        proptest::option::weighted(0.9, (1..5u64).prop_map(|id| SpanId { id }))
+    }
+
+    /// Any message, referring to a small set of subscriber ids
+    fn message() -> impl Strategy<Value = Variant> {
+        prop_oneof![
+            (span_id(), span_id()).prop_map(|(span, parent)| Variant::NewSpan(NewSpan {
+                span,
+                parent,
+                ..NewSpan::default()
+            })),
+            span_id().prop_map(|span| Variant::Record(Record {
+                span,
+                ..Record::default()
+            })),
+            (span_id(), span_id())
+                .prop_map(|(span, follows)| Variant::Follows(RecordFollowsFrom { span, follows })),
+            (span_id(), 0..100i64).prop_map(|(span, nano)| Variant::Event(Event {
+                span,
+                timestamp: Some(Timestamp { nano }),
+                ..Event::default()
+            })),
+            (span_id(), 0..100i64).prop_map(|(span, nano)| Variant::Close(SpanClose {
+                span,
+                timestamp: Some(Timestamp { nano }),
+                ..SpanClose::default()
+            })),
+            vec(1..5u64, 0..3).prop_map(|ids| Variant::Panic(Panic {
+                spans: ids.into_iter().map(|id| SpanId { id }).collect(),
+                ..Panic::default()
+            })),
+        ]
+    }
+
+    /// Spans with unique subscriber ids and their events, in random order
+    fn shuffled_spans() -> impl Strategy<Value = (u64, Vec<Variant>)> {
+        (1..6u64).prop_flat_map(|spans| {
+            vec(1..=spans, 0..30).prop_flat_map(move |events| {
+                let mut messages: Vec<Variant> = (1..=spans).map(new_span).collect();
+                messages.extend(
+                    events
+                        .into_iter()
+                        .enumerate()
+                        .map(|(nano, span)| event(span, nano as i64)),
+                );
+                (Just(spans), Just(messages).prop_shuffle())
+            })
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn arbitrary_messages(messages in vec(message(), 0..200)) {
+            let (handle, _dir) = spilling_store();
+            handle.0.lock().unwrap().retain(Retention {
+                max_events: Some(20),
+                max_spans: Some(4),
+                ..Retention::default()
+            });
+            let mut events = 0;
+            for message in messages {
+                if let Variant::Event(_) = message {
+                    events += 1;
+                }
+                handle.handle(message);
+            }
+
+            // No `InternalId` dangles
+            let store = handle.0.lock().unwrap();
+            prop_assert_eq!(store.event_count(), events);
+            let retained = store.load_events(0..events).unwrap();
+            prop_assert_eq!(retained.len(), store.retained_events());
+            let panics = store.panics().iter().flat_map(|entry| entry.spans.iter());
+            for id in retained.iter().filter_map(|entry| entry.span).chain(panics.cloned()) {
+                prop_assert!(store.load_span(id).unwrap().is_some());
+            }
+            let count = store.span_count();
+            for span in store.spans() {
+                for id in span.parent().into_iter().chain(span.follows().iter().cloned()) {
+                    prop_assert!(store.load_span(id).unwrap().is_some());
+                }
+                // The tree has no cycles
+                prop_assert!(store.ancestors(span.id()).take(count + 1).count() < count);
+            }
+            let anomalies = store.anomalies();
+            prop_assert!(anomalies.backfilled <= anomalies.placeholders);
+        }
+
+        #[test]
+        fn reordered_messages((spans, messages) in shuffled_spans()) {
+            let handle = StoreHandle::new();
+            for message in messages {
+                handle.handle(message);
+            }
+
+            // Every placeholder was backfilled
+            let store = handle.0.lock().unwrap();
+            prop_assert_eq!(store.span_count() as u64, spans);
+            prop_assert!(store.spans().iter().all(|span| !span.is_placeholder()));
+            let anomalies = store.anomalies();
+            prop_assert_eq!(anomalies.placeholders, anomalies.backfilled);
+            for entry in store.hot_events() {
+                let span = store.span(entry.span.unwrap()).unwrap();
+                prop_assert_eq!(&span.span.span, &entry.event.span);
+            }
+        }
     }
 }
@@ -0,0 +1,154 @@
+//! Parent/child links between spans, along with their name, fields and lifecycle
+//!
+//! The tree is built on ingest, see `Store`, and covers all retained spans,
+//! including spilled ones. Traversals, lifecycle and field queries don't need to page in anything.
+use crate::storage::fields::SpanFields;
+use crate::storage::lifecycle::Lifecycle;
+use crate::storage::InternalId;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[derive(Debug, Default)]
+struct Node {
+    name: Option<Arc<str>>,
+    lifecycle: Lifecycle,
+    fields: SpanFields,
+    parent: Option<InternalId>,
+    /// Ordered by id
+    children: Vec<InternalId>,
+    follows: Vec<InternalId>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct SpanTree {
+    nodes: HashMap<InternalId, Node>,
+    /// Span names are shared between all spans of a callsite
+    names: HashMap<String, Arc<str>>,
+}
+
+impl SpanTree {
+    pub(crate) fn insert(&mut self, id: InternalId, parent: Option<InternalId>, created_at: i64) {
+        let node = self.node_mut(id);
+        node.lifecycle.created_at = created_at;
+        self.set_parent(id, parent);
+    }
+
+    pub(crate) fn set_name(&mut self, id: InternalId, name: &str) {
+        let name = match self.names.get(name) {
+            Some(name) => name.clone(),
+            None => {
+                let shared: Arc<str> = Arc::from(name);
+                self.names.insert(name.to_string(), shared.clone());
+                shared
+            }
+        };
+        self.node_mut(id).name = Some(name);
+    }
+
+    /// Links `id` below `parent`, a span is only ever linked once
+    pub(crate) fn set_parent(&mut self, id: InternalId, parent: Option<InternalId>) {
+        let parent = match parent {
+            Some(parent) => parent,
+            None => return,
+        };
+        self.node_mut(id).parent = Some(parent);
+        let children = &mut self.node_mut(parent).children;
+        // Placeholders are backfilled late, so children might arrive out of order
+        if let Err(i) = children.binary_search(&id) {
+            children.insert(i, id);
+        }
+    }
+
+    pub(crate) fn add_follows(&mut self, id: InternalId, follows: InternalId) {
+        self.node_mut(id).follows.push(follows);
+    }
+
+    /// Unlinks an evicted span, returns the spans it referred to
+    pub(crate) fn remove(&mut self, id: InternalId) -> Vec<InternalId> {
+        let node = match self.nodes.remove(&id) {
+            Some(node) => node,
+            None => return Vec::new(),
+        };
+        debug_assert!(node.children.is_empty(), "BUG: Evicted span has children");
+        if let Some(parent) = node.parent.and_then(|parent| self.nodes.get_mut(&parent)) {
+            parent.children.retain(|child| *child != id);
+        }
+        node.parent.into_iter().chain(node.follows).collect()
+    }
+
+    pub(crate) fn name(&self, id: InternalId) -> Option<&str> {
+        self.nodes.get(&id)?.name.as_ref().map(|name| &**name)
+    }
+
+    pub(crate) fn parent(&self, id: InternalId) -> Option<InternalId> {
+        self.nodes.get(&id)?.parent
+    }
+
+    pub(crate) fn children(&self, id: InternalId) -> &[InternalId] {
+        self.nodes
+            .get(&id)
+            .map(|node| node.children.as_slice())
+            .unwrap_or(&[])
+    }
+
+    pub(crate) fn follows(&self, id: InternalId) -> &[InternalId] {
+        self.nodes
+            .get(&id)
+            .map(|node| node.follows.as_slice())
+            .unwrap_or(&[])
+    }
+
+    pub(crate) fn lifecycle(&self, id: InternalId) -> Option<&Lifecycle> {
+        self.nodes.get(&id).map(|node| &node.lifecycle)
+    }
+
+    pub(crate) fn lifecycle_mut(&mut self, id: InternalId) -> &mut Lifecycle {
+        &mut self.node_mut(id).lifecycle
+    }
+
+    pub(crate) fn fields(&self, id: InternalId) -> Option<&SpanFields> {
+        self.nodes.get(&id).map(|node| &node.fields)
+    }
+
+    pub(crate) fn fields_mut(&mut self, id: InternalId) -> &mut SpanFields {
+        &mut self.node_mut(id).fields
+    }
+
+    /// All spans, in no particular order
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (InternalId, Option<&str>, &Lifecycle)> {
+        self.nodes
+            .iter()
+            .map(|(id, node)| (*id, node.name.as_ref().map(|name| &**name), &node.lifecycle))
+    }
+
+    fn node_mut(&mut self, id: InternalId) -> &mut Node {
+        self.nodes.entry(id).or_insert_with(Node::default)
+    }
+}
+
+/// Pre-order traversal below a span, see `Store::descendants`
+pub struct Descendants<'a> {
+    tree: &'a SpanTree,
+    /// Next spans to visit, the top is visited first
+    stack: Vec<InternalId>,
+}
+
+impl<'a> Descendants<'a> {
+    pub(crate) fn new(tree: &'a SpanTree, id: InternalId) -> Descendants<'a> {
+        let mut stack = tree.children(id).to_vec();
+        stack.reverse();
+        Descendants { tree, stack }
+    }
+}
+
+impl<'a> Iterator for Descendants<'a> {
+    type Item = InternalId;
+
+    fn next(&mut self) -> Option<InternalId> {
+        let id = self.stack.pop()?;
+        self.stack
+            .extend(self.tree.children(id).iter().rev().cloned());
+        Some(id)
+    }
+}
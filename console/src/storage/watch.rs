@@ -0,0 +1,115 @@
+//! Change notifications, see `StoreHandle::watch`
+//!
+//! Changes are merged until the watcher takes them, so a slow watcher
+//! receives one summary instead of a backlog of notifications.
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// What changed in the `Store`, since the watcher last took the changes
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Changes {
+    /// Events at this index and later were added, see `Store::load_events`
+    ///
+    /// Events are only appended, late ones as well, see `Store::hot_events`.
+    pub events_from: Option<usize>,
+    /// Older events were evicted, see `Store::first_event`
+    pub evicted: bool,
+    /// Spans were added, updated or closed
+    pub spans: bool,
+    /// Panics, subscriber stats or process samples arrived, or building an index failed
+    pub status: bool,
+}
+
+impl Changes {
+    /// Everything might have changed, e.g. for a new watcher
+    pub fn all() -> Changes {
+        Changes {
+            events_from: Some(0),
+            evicted: true,
+            spans: true,
+            status: true,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Changes::default()
+    }
+
+    pub(crate) fn merge(&mut self, other: &Changes) {
+        self.events_from = match (self.events_from, other.events_from) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        self.evicted |= other.evicted;
+        self.spans |= other.spans;
+        self.status |= other.status;
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Pending {
+    changes: Mutex<Changes>,
+    changed: Condvar,
+}
+
+impl Pending {
+    pub(crate) fn notify(&self, changes: &Changes) {
+        self.changes.lock().unwrap().merge(changes);
+        self.changed.notify_all();
+    }
+}
+
+/// Receives the changes of a `Store`, see `StoreHandle::watch`
+#[derive(Debug)]
+pub struct Watcher {
+    pending: Arc<Pending>,
+}
+
+impl Watcher {
+    pub(crate) fn new() -> (Watcher, Arc<Pending>) {
+        let pending = Arc::new(Pending {
+            changes: Mutex::new(Changes::all()),
+            changed: Condvar::new(),
+        });
+        (
+            Watcher {
+                pending: pending.clone(),
+            },
+            pending,
+        )
+    }
+
+    /// Takes the changes so far, they might be empty
+    pub fn take(&self) -> Changes {
+        let mut changes = self.pending.changes.lock().unwrap();
+        std::mem::replace(&mut *changes, Changes::default())
+    }
+
+    /// Blocks until something changed
+    pub fn wait(&self) -> Changes {
+        let mut changes = self.pending.changes.lock().unwrap();
+        while changes.is_empty() {
+            changes = self.pending.changed.wait(changes).unwrap();
+        }
+        std::mem::replace(&mut *changes, Changes::default())
+    }
+
+    /// Like `wait`, returns empty changes once `timeout` elapsed
+    pub fn wait_timeout(&self, timeout: Duration) -> Changes {
+        let deadline = Instant::now() + timeout;
+        let mut changes = self.pending.changes.lock().unwrap();
+        while changes.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            changes = self
+                .pending
+                .changed
+                .wait_timeout(changes, deadline - now)
+                .unwrap()
+                .0;
+        }
+        std::mem::replace(&mut *changes, Changes::default())
+    }
+}
@@ -1,7 +1,8 @@
-use crate::storage::StoreHandle;
+use crate::storage::{Changes, Store, StoreHandle, Watcher};
 
 use tui::backend::CrosstermBackend;
 use tui::layout::{Alignment, Constraint, Direction, Layout, Rect};
+use tui::style::{Color, Modifier, Style};
 use tui::widgets::{Paragraph, Text, Widget};
 use tui::Frame;
 use tui::Terminal;
@@ -10,9 +11,13 @@ use crossterm::{InputEvent, KeyEvent, MouseEvent, RawScreen};
 
 use crate::filter::*;
 use crate::ui::Command;
-use crate::ui::{Action, EventList, Hitbox, Input, QueryView};
+use crate::ui::{
+    format_bytes, Action, DiagnosticsView, EventList, Hitbox, Input, ProcessView, QueryView,
+    SpanView,
+};
 
 use std::cell::Cell;
+use std::fmt::Write;
 use std::sync::mpsc;
 use std::thread;
 use std::time::Duration;
@@ -23,12 +28,15 @@ pub(crate) enum Focus {
     Query,
 }
 
+/// Changes arriving within this interval are drawn at once
+const FRAME_INTERVAL: Duration = Duration::from_millis(50);
+
 enum Event {
     Input(InputEvent),
-    Update,
+    Update(Changes),
 }
 
-fn setup_input_handling() -> mpsc::Receiver<Event> {
+fn setup_input_handling(watcher: Watcher) -> mpsc::Receiver<Event> {
     // Setup input handling
     let (tx, rx) = mpsc::channel();
     {
@@ -51,15 +59,14 @@ fn setup_input_handling() -> mpsc::Receiver<Event> {
         });
     }
 
-    // Setup 250ms tick rate
+    // Setup store updates
     {
         let tx = tx.clone();
-        thread::spawn(move || {
-            let tx = tx.clone();
-            loop {
-                tx.send(Event::Update).unwrap();
-                thread::sleep(Duration::from_millis(250));
+        thread::spawn(move || loop {
+            if tx.send(Event::Update(watcher.wait())).is_err() {
+                return;
             }
+            thread::sleep(FRAME_INTERVAL);
         });
     }
     rx
@@ -71,28 +78,43 @@ pub struct App {
 
     event_list: EventList,
     query_view: QueryView,
+    process_view: ProcessView,
+    diagnostics_view: DiagnosticsView,
+    span_view: SpanView,
 
     filter: Filter,
     filter_updated: bool,
 
+    /// Banner text of the most recent panic
+    panic: Option<String>,
+    /// Status line text of the subscriber stats
+    stats: Option<String>,
+
     rect: Cell<Option<(Rect, Rect)>>,
     rx: mpsc::Receiver<Event>,
 }
 
 impl App {
     pub fn new(store: StoreHandle) -> Result<App, failure::Error> {
+        let rx = setup_input_handling(store.watch());
         Ok(App {
             store,
             focus: Focus::Query,
 
             event_list: EventList::new(),
             query_view: QueryView::new(),
+            process_view: ProcessView::new(),
+            diagnostics_view: DiagnosticsView::new(),
+            span_view: SpanView::new(),
 
             filter: Filter::default(),
             filter_updated: false,
 
+            panic: None,
+            stats: None,
+
             rect: Cell::new(None),
-            rx: setup_input_handling(),
+            rx,
         })
     }
 
@@ -107,12 +129,14 @@ impl App {
             let draw = match self.rx.recv()? {
                 Event::Input(event) => {
                     if let Some(redraw) = self.input(event) {
-                        redraw
+                        // Input might require paging in events, or show another span
+                        let update = self.update(&Changes::default());
+                        redraw || update
                     } else {
                         break;
                     }
                 }
-                Event::Update => self.update(),
+                Event::Update(changes) => self.update(&changes),
             };
             if draw {
                 terminal.draw(|mut f| {
@@ -140,18 +164,93 @@ impl App {
         Ok(())
     }
 
-    pub fn update(&mut self) -> bool {
-        let store = self.store.0.lock().unwrap();
-        if store.updated() || self.filter_updated {
-            let event_list = self.event_list.update(&store, &self.filter);
+    /// Updates the views affected by `changes`, returns if the scene has to be redrawn
+    pub fn update(&mut self, changes: &Changes) -> bool {
+        // The views are updated while the store is locked
+        let handle = self.store.clone();
+        let store = handle.0.lock().unwrap();
+        let mut rerender = false;
+        // Span fields decide which events match as well
+        let events = changes.events_from.is_some()
+            || changes.evicted
+            || (changes.spans && !self.filter.span_modifier.is_empty())
+            || self.filter_updated
+            || self.event_list.wants_update();
+        if events {
+            rerender |= self.event_list.update(&store, &self.filter, changes);
+        }
+        if self.filter_updated {
             self.filter_updated = false;
-            let query_view = self.query_view.update(self.filter.clone());
-
-            let rerender = event_list || query_view;
-            rerender
-        } else {
-            false
+            rerender |= self.query_view.update(self.filter.clone());
+        }
+        if changes.status {
+            rerender |= self.update_panic(&store);
+            rerender |= self.update_stats(&store);
+            rerender |= self.process_view.update(&store);
+        }
+        if !changes.is_empty() {
+            // Any message might have been an anomaly
+            rerender |= self.diagnostics_view.update(&store);
         }
+        let selected_span = self.event_list.selected_span();
+        if changes.spans || events || self.span_view.wants_update(selected_span) {
+            rerender |= self.span_view.update(&store, selected_span);
+        }
+        rerender
+    }
+
+    /// Speeds up filtering on field `name`, see `StoreHandle::index_field`
+    fn index_field(&self, name: &str) {
+        // Reading spilled events mustn't block the UI, filters scan the events until it's done.
+        // Failures are shown as diagnostics, see `Store::index_error`
+        let store = self.store.clone();
+        let name = name.to_string();
+        thread::spawn(move || store.index_field(&name));
+    }
+
+    fn update_panic(&mut self, store: &Store) -> bool {
+        let panic = store.panics().last().map(|entry| {
+            let panic = &entry.panic;
+            let mut text = format!(" PANIC in thread '{}'", panic.thread_name);
+            if let (Some(line), Some(column)) = (&panic.line, &panic.column) {
+                write!(text, " at {}:{}:{}", panic.file, line.num, column.num).unwrap();
+            }
+            write!(text, ": {}", panic.payload).unwrap();
+            if !entry.spans.is_empty() {
+                write!(text, " ({} spans entered)", entry.spans.len()).unwrap();
+            }
+            text
+        });
+        let rerender = self.panic != panic;
+        self.panic = panic;
+        rerender
+    }
+
+    fn update_stats(&mut self, store: &Store) -> bool {
+        let stats = store.stats().map(|stats| {
+            let latency = stats
+                .consoles
+                .iter()
+                .map(|console| console.send_latency_micros)
+                .max()
+                .unwrap_or(0);
+            format!(
+                " subscriber: {:.0} events/s, queue {}, latency {}µs, sent {}, dropped {}, {} spans",
+                stats.events_per_sec,
+                stats.queue_depth,
+                latency,
+                format_bytes(stats.bytes_sent),
+                stats.dropped,
+                stats.registry_size,
+            )
+        });
+        let stats = match (stats, store.subscriber_errors().last()) {
+            (Some(stats), Some(error)) => Some(format!("{}, error: {}", stats, error)),
+            (stats, _) => stats,
+        };
+        let rerender = self.stats != stats;
+        self.stats = stats;
+        rerender
     }
 
     fn show_cursor(&self) -> Option<(u16, u16)> {
@@ -221,9 +320,18 @@ impl App {
                     let redraw = action.redraw();
                     match action {
                         Action::Command(Command::Event(modifier)) => {
+                            // Lookups by value are likely repeated, e.g. for an id
+                            if let crate::filter::Modifier::FieldEquals { name, .. } = &modifier {
+                                self.index_field(name);
+                            }
                             self.filter.insert_modifier(modifier);
                             self.filter_updated = true;
                         }
+                        Action::Command(Command::Span(modifier)) => {
+                            self.filter.insert_span_modifier(modifier);
+                            self.filter_updated = true;
+                        }
+                        Action::Command(Command::Index(name)) => self.index_field(&name),
                         _ => {}
                     }
                     redraw
@@ -260,23 +368,95 @@ impl App {
     pub fn render_to(&mut self, f: &mut Frame<CrosstermBackend>) {
         let mut rect = f.size();
         let mut legend_rect = rect;
-        // Reserve space for legend
-        rect.height -= 1;
-        legend_rect.y += legend_rect.height - 1;
+        // Reserve space for legend, tiny terminals get whatever fits
+        rect.height = rect.height.saturating_sub(1);
+        legend_rect.y += legend_rect.height.saturating_sub(1);
         legend_rect.height = 1;
 
+        if let Some(stats) = &self.stats {
+            // Reserve space for the status line, right above the legend
+            let mut stats_rect = legend_rect;
+            rect.height = rect.height.saturating_sub(1);
+            stats_rect.y = rect.y + rect.height;
+            Paragraph::new([Text::raw(stats.as_str())].iter())
+                .style(Style::default().fg(Color::DarkGray))
+                .render(f, stats_rect);
+        }
+
+        if let Some(panic) = &self.panic {
+            // Reserve space for the panic banner, above the status line
+            let mut panic_rect = legend_rect;
+            rect.height = rect.height.saturating_sub(1);
+            panic_rect.y = rect.y + rect.height;
+            Paragraph::new([Text::raw(panic.as_str())].iter())
+                .style(
+                    Style::default()
+                        .fg(Color::White)
+                        .bg(Color::Red)
+                        .modifier(Modifier::BOLD),
+                )
+                .render(f, panic_rect);
+        }
+
         let chunks = Layout::default()
             .constraints([Constraint::Length(50), Constraint::Min(10)].as_ref())
             .direction(Direction::Horizontal)
             .split(rect);
 
-        self.query_view.render_to(f, chunks[0]);
-        self.event_list.render_to(f, chunks[1]);
+        let event_rect = if self.process_view.visible() {
+            // Charts on top of the events, to correlate them
+            let right = Layout::default()
+                .constraints([Constraint::Length(6), Constraint::Min(5)].as_ref())
+                .direction(Direction::Vertical)
+                .split(chunks[1]);
+            self.process_view.render_to(f, right[0]);
+            right[1]
+        } else {
+            chunks[1]
+        };
+        let event_rect = if self.span_view.visible() {
+            // Below the events, next to the selection
+            let right = Layout::default()
+                .constraints(
+                    [
+                        Constraint::Min(5),
+                        Constraint::Length(self.span_view.height().min(12)),
+                    ]
+                    .as_ref(),
+                )
+                .direction(Direction::Vertical)
+                .split(event_rect);
+            self.span_view.render_to(f, right[1]);
+            right[0]
+        } else {
+            event_rect
+        };
+
+        let query_rect = if self.diagnostics_view.visible() {
+            // Below the queries, out of the way of the events
+            let left = Layout::default()
+                .constraints(
+                    [
+                        Constraint::Min(5),
+                        Constraint::Length(self.diagnostics_view.height()),
+                    ]
+                    .as_ref(),
+                )
+                .direction(Direction::Vertical)
+                .split(chunks[0]);
+            self.diagnostics_view.render_to(f, left[1]);
+            left[0]
+        } else {
+            chunks[0]
+        };
+
+        self.query_view.render_to(f, query_rect);
+        self.event_list.render_to(f, event_rect);
         Paragraph::new([Text::raw(" q: close, ← → ↑ ↓ click: navigate")].iter())
             .render(f, legend_rect);
         Paragraph::new([Text::raw("prerelease version ")].iter())
             .alignment(Alignment::Right)
             .render(f, legend_rect);
-        self.rect.set(Some((chunks[0], chunks[1])));
+        self.rect.set(Some((query_rect, event_rect)));
     }
 }
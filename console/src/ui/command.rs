@@ -4,6 +4,10 @@ use std::str::FromStr;
 #[derive(Debug, PartialEq)]
 pub(crate) enum Command {
     Event(Modifier),
+    /// Matches the fields of the event's span, see `Filter::span_modifier`
+    Span(Modifier),
+    /// Indexes an event field, see `StoreHandle::index_field`
+    Index(String),
 }
 
 impl FromStr for Command {
@@ -18,20 +22,36 @@ impl Command {
         let command_end = string.find(char::is_whitespace)?;
         let (command_str, remaining) = string.split_at(command_end);
         match command_str {
-            _ if command_str.starts_with("event.") => Command::parse_event(command_str, remaining),
+            _ if command_str.starts_with("event.") => Some(Command::Event(Command::parse_field(
+                "event",
+                command_str,
+                remaining,
+            )?)),
+            _ if command_str.starts_with("span.") => Some(Command::Span(Command::parse_field(
+                "span",
+                command_str,
+                remaining,
+            )?)),
+            "index" => Some(Command::Index(Command::parse_name(remaining)?)),
             _ => None,
         }
     }
 
-    fn parse_event(command: &str, remaining: &str) -> Option<Command> {
+    fn parse_name(remaining: &str) -> Option<String> {
+        let name = remaining.trim();
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return None;
+        }
+        Some(name.to_string())
+    }
+
+    fn parse_field(scope: &str, command: &str, remaining: &str) -> Option<Modifier> {
         let mut segments = command.split('.');
-        if !(segments.next() == Some("event") && segments.next() == Some("field")) {
+        if !(segments.next() == Some(scope) && segments.next() == Some("field")) {
             return None;
         }
         let fieldname = segments.next()?;
-        Some(Command::Event(Command::parse_operator(
-            fieldname, remaining,
-        )?))
+        Command::parse_operator(fieldname, remaining)
     }
 
     fn parse_operator(fieldname: &str, mut remaining: &str) -> Option<Modifier> {
@@ -94,4 +114,24 @@ mod tests {
             )))
         )
     }
+
+    #[test]
+    fn parse_span_command() {
+        assert_eq!(
+            r#"span.field.status == "done""#.parse(),
+            Ok(Command::Span(Modifier::equals(
+                "status".to_string(),
+                "done".to_string()
+            )))
+        )
+    }
+
+    #[test]
+    fn parse_index_command() {
+        assert_eq!(
+            "index conn_id".parse(),
+            Ok(Command::Index("conn_id".to_string()))
+        );
+        assert_eq!("index conn_id message".parse::<Command>(), Err(()));
+    }
 }
@@ -0,0 +1,83 @@
+use crate::storage::*;
+
+use tui::backend::CrosstermBackend;
+use tui::layout::Rect;
+use tui::style::{Color, Style};
+use tui::widgets::{Block, Borders, Paragraph, Text, Widget};
+use tui::Frame;
+
+/// Anomalies the `Store` ran into, e.g. messages for unknown spans, and index failures
+pub struct DiagnosticsView {
+    anomalies: Anomalies,
+    /// Spans still waiting for their `NewSpan`
+    placeholders: usize,
+    /// See `Store::index_error`
+    index_error: Option<String>,
+}
+
+impl DiagnosticsView {
+    pub(crate) fn new() -> DiagnosticsView {
+        DiagnosticsView {
+            anomalies: Anomalies::default(),
+            placeholders: 0,
+            index_error: None,
+        }
+    }
+
+    /// The view is only shown, once something went wrong
+    pub(crate) fn visible(&self) -> bool {
+        self.anomalies.any() || self.index_error.is_some()
+    }
+
+    /// Rows including the borders
+    pub(crate) fn height(&self) -> u16 {
+        if self.index_error.is_some() {
+            6
+        } else {
+            5
+        }
+    }
+
+    pub(crate) fn update(&mut self, store: &Store) -> bool {
+        let anomalies = store.anomalies();
+        let index_error = store.index_error();
+        if *anomalies == self.anomalies
+            && index_error == self.index_error.as_ref().map(String::as_str)
+        {
+            return false;
+        }
+        self.index_error = index_error.map(str::to_string);
+        self.anomalies = anomalies.clone();
+        self.placeholders = store
+            .spans()
+            .iter()
+            .filter(|span| span.is_placeholder())
+            .count();
+        true
+    }
+
+    pub(crate) fn render_to(&self, f: &mut Frame<CrosstermBackend>, r: Rect) {
+        let mut lines = vec![
+            Text::raw(format!(
+                "{} spans unknown, {} backfilled\n",
+                self.anomalies.placeholders, self.anomalies.backfilled
+            )),
+            Text::raw(format!("{} still missing\n", self.placeholders)),
+            Text::raw(format!(
+                "{} malformed messages dropped\n",
+                self.anomalies.malformed
+            )),
+        ];
+        if let Some(error) = &self.index_error {
+            lines.push(Text::raw(format!("{}\n", error)));
+        }
+        Paragraph::new(lines.iter())
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .border_style(Style::default().fg(Color::Yellow))
+                    .title("Diagnostics"),
+            )
+            .render(f, r);
+    }
+}
@@ -1,7 +1,7 @@
 use crate::storage::*;
 
 use crate::filter::*;
-use crate::ui::{Hitbox, Input};
+use crate::ui::{format_count, format_duration, Hitbox, Input};
 
 use tui::backend::CrosstermBackend;
 use tui::layout::Rect;
@@ -10,12 +10,48 @@ use tui::widgets::{Block, Borders, Paragraph, Text, Widget};
 use tui::Frame;
 
 use std::cell::Cell;
+use std::collections::HashMap;
 use std::fmt::Write;
+use std::ops::Range;
+use std::time::Duration;
 
+/// Number of spilled events, which are paged in at once when scrolling back
+const PAGE_EVENTS: usize = 10_000;
+/// Rows taken from the store, before the frame size is known
+const DEFAULT_ROWS: usize = 100;
+
+/// Rows are the matching spilled events, which were paged in, followed by the
+/// matching events in memory, each ordered by timestamp, see `sort_timeline`.
+/// Only the rows within the frame are copied out of the store.
 pub struct EventList {
-    /// Cached rows, gets populated by `EventList::update`
-    logs: Vec<EventEntry>,
-    /// Index into logs vec, indicates which row the user selected
+    /// Indices of the matching events in memory, ordered by timestamp
+    hot: Vec<usize>,
+    /// Events in memory before this index were filtered already
+    filtered_to: usize,
+    /// Filter the rows were created with
+    filter: Option<Filter>,
+
+    /// Unless the user scrolled back, spilled events are dropped from the list
+    following: bool,
+    /// Paged in, matching spilled events within `window_from..cold_to`
+    cold: Vec<(usize, EventEntry)>,
+    window_from: usize,
+    cold_to: usize,
+    /// The user scrolled past the first row
+    load_older: bool,
+    /// Events before were evicted, see `Retention`
+    first_event: usize,
+    /// Time span and number of retained events, if the store evicts any
+    retained: Option<(Duration, usize)>,
+    /// Rows are tagged with the name of their source, once there are several
+    sources: HashMap<SourceId, String>,
+
+    /// Rows within the frame and their index in the `Store`, see `EventList::update`
+    visible: Vec<(usize, EventEntry)>,
+    /// Rows `visible` was taken from
+    window: Range<usize>,
+
+    /// Indicates which row the user selected
     selection: usize,
     /// How far the frame is offset by scrolling
     offset: usize,
@@ -28,7 +64,21 @@ impl EventList {
     pub(crate) fn new() -> EventList {
         EventList {
             focused: false,
-            logs: Vec::new(),
+            hot: Vec::new(),
+            filtered_to: 0,
+            filter: None,
+
+            following: true,
+            cold: Vec::new(),
+            window_from: 0,
+            cold_to: 0,
+            load_older: false,
+            first_event: 0,
+            retained: None,
+            sources: HashMap::new(),
+
+            visible: Vec::new(),
+            window: 0..0,
 
             selection: 0,
             offset: 0,
@@ -36,15 +86,197 @@ impl EventList {
         }
     }
 
-    pub(crate) fn update(&mut self, store: &Store, filter: &Filter) -> bool {
-        let logs = store
-            .events()
-            .iter()
-            .filter(|entry| filter.filter(entry))
-            .cloned()
+    /// Span of the selected event
+    pub(crate) fn selected_span(&self) -> Option<InternalId> {
+        let row = self.selection.checked_sub(self.window.start)?;
+        self.visible.get(row)?.1.span
+    }
+
+    /// Whether `update` has work to do, even if the store didn't change
+    pub(crate) fn wants_update(&self) -> bool {
+        self.load_older || self.window != self.frame()
+    }
+
+    fn rows(&self) -> usize {
+        self.cold.len() + self.hot.len()
+    }
+
+    /// Index of the event in row `row`
+    fn index(&self, row: usize) -> Option<usize> {
+        match self.cold.get(row) {
+            Some((index, _)) => Some(*index),
+            None => self.hot.get(row - self.cold.len()).cloned(),
+        }
+    }
+
+    /// Timestamp and index of the selected event, which is how rows are ordered
+    fn selected(&self, store: &Store) -> Option<(i64, usize)> {
+        if let Some((index, entry)) = self.cold.get(self.selection) {
+            return Some((entry.timestamp(), *index));
+        }
+        let index = self.index(self.selection)?;
+        if index >= store.cold_events() {
+            return Some(hot_key(store, index));
+        }
+        // Spilled or evicted since the last update
+        let (index, entry) = self.visible.iter().find(|(i, _)| *i == index)?;
+        Some((entry.timestamp(), *index))
+    }
+
+    /// Row of the event with `key`, or of the next one if it doesn't match
+    fn row(&self, store: &Store, key: (i64, usize)) -> usize {
+        match self
+            .cold
+            .binary_search_by_key(&key, |(index, entry)| (entry.timestamp(), *index))
+        {
+            Ok(row) => row,
+            Err(row) if row < self.cold.len() => row,
+            Err(row) => match self
+                .hot
+                .binary_search_by_key(&key, |&index| hot_key(store, index))
+            {
+                Ok(hot) | Err(hot) => row + hot,
+            },
+        }
+    }
+
+    /// Number of rows fitting into the frame
+    fn frame_rows(&self) -> usize {
+        // - 2: Upper and lower border of window
+        self.rect.get().map_or(DEFAULT_ROWS, |rect| {
+            (rect.height as usize).saturating_sub(2)
+        })
+    }
+
+    /// Rows within the frame
+    fn frame(&self) -> Range<usize> {
+        self.offset..(self.offset + self.frame_rows()).min(self.rows())
+    }
+
+    /// Updates the rows with the `changes` of the store
+    ///
+    /// Events in memory are only filtered once, unless the filter changed.
+    pub(crate) fn update(&mut self, store: &Store, filter: &Filter, changes: &Changes) -> bool {
+        let hot_start = store.cold_events();
+        let selected = self.selected(store);
+        let rows = self.rows();
+        let mut filter_from = changes
+            .events_from
+            .map_or(self.filtered_to, |from| from.min(self.filtered_to));
+        self.first_event = store.first_event();
+        self.retained = match store.time_window() {
+            Some((oldest, latest)) if store.retention().is_limited() => Some((
+                Duration::from_nanos((latest - oldest).max(0) as u64),
+                store.retained_events(),
+            )),
+            _ => None,
+        };
+        if store.sources().nth(1).is_some() {
+            self.sources = store
+                .sources()
+                .map(|(id, name)| (id, name.to_string()))
+                .collect();
+        }
+        if self.filter.as_ref() != Some(filter) {
+            self.filter = Some(filter.clone());
+            self.following = true;
+            filter_from = 0;
+        } else if changes.spans && !filter.span_modifier.is_empty() {
+            // Events might match other span fields now
+            filter_from = 0;
+        }
+        // Evicted events can't be paged in again
+        if self.window_from < self.first_event {
+            let first_event = self.first_event;
+            self.cold.retain(|(index, _)| *index >= first_event);
+            self.window_from = first_event;
+            self.cold_to = self.cold_to.max(first_event);
+        }
+        if self.load_older && self.window_from > self.first_event {
+            self.following = false;
+            let from = self
+                .window_from
+                .saturating_sub(PAGE_EVENTS)
+                .max(self.first_event);
+            let mut older = load_filtered(store, filter, from..self.window_from);
+            older.append(&mut self.cold);
+            sort_timeline(&mut older);
+            self.cold = older;
+            self.window_from = from;
+        }
+        self.load_older = false;
+        if self.following {
+            self.cold.clear();
+            self.window_from = hot_start;
+            self.cold_to = hot_start;
+        } else if self.cold_to < hot_start {
+            // Spilled since the last update
+            let spilled = load_filtered(store, filter, self.cold_to..hot_start);
+            self.cold.extend(spilled);
+            sort_timeline(&mut self.cold);
+            self.cold_to = hot_start;
+        }
+
+        // Spilled or evicted events are dropped, new ones are filtered
+        let filter_from = filter_from.max(hot_start);
+        self.hot
+            .retain(|&index| index >= hot_start && index < filter_from);
+        let events = store.hot_events();
+        let hot_end = hot_start + events.len();
+        let mut matching: Vec<usize> = match filter.candidates(store, filter_from..hot_end) {
+            // Indexed fields narrow down the events to check, see `StoreHandle::index_field`
+            Some(candidates) => candidates
+                .into_iter()
+                .filter(|index| filter.filter(store, &events[index - hot_start]))
+                .collect(),
+            None => events
+                .iter()
+                .enumerate()
+                .skip(filter_from - hot_start)
+                .filter(|(_, entry)| filter.filter(store, entry))
+                .map(|(i, _)| hot_start + i)
+                .collect(),
+        };
+        matching.sort_by_key(|&index| hot_key(store, index));
+        let late = match (self.hot.last(), matching.first()) {
+            (Some(&last), Some(&first)) => hot_key(store, first) < hot_key(store, last),
+            _ => false,
+        };
+        self.hot.append(&mut matching);
+        if late {
+            // Sources aren't in sync, the stable sort merges both runs
+            self.hot.sort_by_key(|&index| hot_key(store, index));
+        }
+        self.filtered_to = hot_end;
+
+        // Keep the selected event in place, rows might have been added or removed above it
+        if let Some(selected) = selected {
+            let above = self.selection.saturating_sub(self.offset);
+            let row = self.row(store, selected);
+            self.selection = row.min(self.rows().saturating_sub(1));
+            self.offset = self.selection.saturating_sub(above);
+        }
+        self.take_visible(store) || rows != self.rows()
+    }
+
+    /// Copies the rows within the frame out of the store
+    fn take_visible(&mut self, store: &Store) -> bool {
+        let hot_start = store.cold_events();
+        let window = self.frame();
+        let visible: Vec<_> = window
+            .clone()
+            .filter_map(|row| match self.cold.get(row) {
+                Some(entry) => Some(entry.clone()),
+                None => {
+                    let index = self.index(row)?;
+                    let entry = store.hot_events().get(index - hot_start)?;
+                    Some((index, entry.clone()))
+                }
+            })
             .collect();
-        let rerender = self.logs != logs;
-        self.logs = logs;
+        let rerender = self.visible != visible;
+        self.visible = visible;
+        self.window = window;
         rerender
     }
 
@@ -55,7 +287,7 @@ impl EventList {
     /// the selection has "space" to move without adjusting
     fn adjust_window_to_selection(&mut self) -> bool {
         // Calc the largest index that will be still in frame
-        let rowcount = self.rect.get().unwrap_or_default().height as usize - 2;
+        let rowcount = self.frame_rows();
         let upper_limit = self.offset + rowcount;
 
         if self.selection < self.offset {
@@ -73,6 +305,9 @@ impl EventList {
     }
 
     pub(crate) fn on_up(&mut self) -> bool {
+        if self.selection == 0 && self.window_from > self.first_event {
+            self.load_older = true;
+        }
         let new_offset = self.selection.saturating_sub(1);
         self.select(new_offset)
     }
@@ -83,8 +318,8 @@ impl EventList {
     }
 
     fn select(&mut self, mut new_offset: usize) -> bool {
-        if self.logs.len() < new_offset {
-            new_offset = self.logs.len() - 1;
+        if self.rows() <= new_offset {
+            new_offset = self.rows().saturating_sub(1);
         }
 
         let rerender = new_offset != self.selection;
@@ -105,6 +340,9 @@ impl EventList {
             Some(Level::Warn) => Text::styled(" WARN ", Style::default().fg(Color::Yellow)),
         };
         let mut text = String::new();
+        if let Some(source) = self.sources.get(&entry.source) {
+            write!(text, "[{}] ", source).unwrap();
+        }
         let mut first = true;
         for value in &entry.event.values {
             if first {
@@ -142,19 +380,32 @@ impl EventList {
         let rowcount = r.height as usize - 2;
 
         let (border_color, title_color) = self.border_color();
-        let block_title = format!(
+        let mut block_title = format!(
             "Events {}-{}/{}",
             1 + self.offset,
-            self.offset + std::cmp::min(rowcount, self.logs.len()),
-            self.logs.len(),
+            self.offset + std::cmp::min(rowcount, self.rows()),
+            self.rows(),
         );
+        if self.window_from > self.first_event {
+            let older = self.window_from - self.first_event;
+            write!(block_title, " ({} older on disk)", older).unwrap();
+        }
+        if let Some((window, count)) = self.retained {
+            write!(
+                block_title,
+                " showing last {} / {} events",
+                format_duration(window),
+                format_count(count)
+            )
+            .unwrap();
+        }
+        // Taken by the last `update`, which follows any scrolling
         Paragraph::new(
-            self.logs
+            self.visible
                 .iter()
-                .skip(self.offset)
                 .take(rowcount)
                 .enumerate()
-                .map(|(i, e)| self.style_event(i, e))
+                .map(|(i, (_, e))| self.style_event(i, e))
                 .flatten()
                 .collect::<Vec<Text<'_>>>()
                 .iter(),
@@ -170,6 +421,31 @@ impl EventList {
     }
 }
 
+/// Timestamp and index of the event in memory at `index`, which is how rows are ordered
+fn hot_key(store: &Store, index: usize) -> (i64, usize) {
+    let entry = &store.hot_events()[index - store.cold_events()];
+    (entry.timestamp(), index)
+}
+
+/// Matching events within `range`, along with their index
+///
+/// A failed read only hides the spilled events.
+fn load_filtered(store: &Store, filter: &Filter, range: Range<usize>) -> Vec<(usize, EventEntry)> {
+    let start = range.start;
+    let events = match filter.candidates(store, range.clone()) {
+        // Indexed fields narrow down the spilled events to read
+        Some(candidates) => store.load_events_at(&candidates),
+        None => store
+            .load_events(range)
+            .map(|events| (start..).zip(events).collect()),
+    };
+    events
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(_, entry)| filter.filter(store, entry))
+        .collect()
+}
+
 impl Input for EventList {
     fn set_focused(&mut self, focused: bool) {
         self.focused = focused;
@@ -1,16 +1,24 @@
 pub(crate) mod app;
 pub(crate) mod command;
+pub(crate) mod diagnostics;
 pub(crate) mod events;
+pub(crate) mod process;
 pub(crate) mod query;
+pub(crate) mod span;
 
 pub use self::app::*;
 pub(crate) use self::command::*;
+pub(crate) use self::diagnostics::*;
 pub(crate) use self::events::*;
+pub(crate) use self::process::*;
 pub(crate) use self::query::*;
+pub(crate) use self::span::*;
 
 use tui::layout::Rect;
 use tui::style::Color;
 
+use std::time::Duration;
+
 pub(crate) enum Action {
     Command(Command),
     Redraw,
@@ -63,3 +71,44 @@ impl Hitbox for Rect {
             && (self.y..(self.y + self.height)).contains(&y)
     }
 }
+
+/// Human readable size, e.g. `1.2 MiB`
+pub(crate) fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Human readable count, e.g. `1.2M`
+pub(crate) fn format_count(count: usize) -> String {
+    match count {
+        0..=999 => count.to_string(),
+        1_000..=999_999 => format!("{:.1}k", count as f64 / 1e3),
+        _ => format!("{:.1}M", count as f64 / 1e6),
+    }
+}
+
+/// Human readable duration at the coarsest unit, e.g. `2h`
+pub(crate) fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m", secs / 60),
+        _ => format!("{}h", secs / 3600),
+    }
+}
+
+/// Time of day in UTC, e.g. `12:03:04`
+pub(crate) fn format_time(nano: i64) -> String {
+    let secs = nano.max(0) / 1_000_000_000 % 86_400;
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
@@ -0,0 +1,89 @@
+use crate::storage::*;
+use crate::ui::format_bytes;
+
+use tui::backend::CrosstermBackend;
+use tui::layout::{Constraint, Direction, Layout, Rect};
+use tui::style::{Color, Style};
+use tui::widgets::{Block, Borders, Sparkline, Widget};
+use tui::Frame;
+
+/// Charts of the `ProcessSample`s sent by the subscriber
+pub struct ProcessView {
+    /// CPU usage in percent, one entry per sample
+    cpu: Vec<u64>,
+    /// RSS in KiB, one entry per sample
+    rss: Vec<u64>,
+    latest: Option<ProcessSample>,
+}
+
+impl ProcessView {
+    pub(crate) fn new() -> ProcessView {
+        ProcessView {
+            cpu: Vec::new(),
+            rss: Vec::new(),
+            latest: None,
+        }
+    }
+
+    /// The view is only shown, once the subscriber sent samples
+    pub(crate) fn visible(&self) -> bool {
+        self.latest.is_some()
+    }
+
+    pub(crate) fn update(&mut self, store: &Store) -> bool {
+        let samples = store.process_samples();
+        if samples.len() == self.cpu.len() {
+            return false;
+        }
+        for sample in &samples[self.cpu.len()..] {
+            self.cpu.push((sample.cpu_usage * 100.0).round() as u64);
+            self.rss.push(sample.rss_bytes / 1024);
+        }
+        self.latest = samples.last().cloned();
+        true
+    }
+
+    pub(crate) fn render_to(&self, f: &mut Frame<CrosstermBackend>, r: Rect) {
+        let latest = match &self.latest {
+            Some(latest) => latest,
+            None => return,
+        };
+        let chunks = Layout::default()
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+            .direction(Direction::Horizontal)
+            .split(r);
+
+        let cpu_title = format!(
+            "CPU {:.0}% ({} threads)",
+            latest.cpu_usage * 100.0,
+            latest.threads
+        );
+        let cpu = ProcessView::window(&self.cpu, chunks[0]);
+        Sparkline::default()
+            .block(Block::default().borders(Borders::ALL).title(&cpu_title))
+            .data(cpu)
+            // Don't exaggerate idle processes, multiple cores might exceed 100% though
+            .max(cpu.iter().cloned().max().unwrap_or(0).max(100))
+            .style(Style::default().fg(Color::Green))
+            .render(f, chunks[0]);
+
+        let rss_title = format!(
+            "RSS {} ({} fds)",
+            format_bytes(latest.rss_bytes),
+            latest.open_fds
+        );
+        let rss = ProcessView::window(&self.rss, chunks[1]);
+        Sparkline::default()
+            .block(Block::default().borders(Borders::ALL).title(&rss_title))
+            .data(rss)
+            .style(Style::default().fg(Color::Cyan))
+            .render(f, chunks[1]);
+    }
+
+    /// The most recent values, which fit into `r`
+    fn window(data: &[u64], r: Rect) -> &[u64] {
+        // - 2: Left and right border
+        let width = r.width.saturating_sub(2) as usize;
+        &data[data.len().saturating_sub(width)..]
+    }
+}
@@ -83,9 +83,11 @@ impl QueryView {
 
     pub(crate) fn render_to(&self, f: &mut Frame<CrosstermBackend>, r: Rect) {
         let (border_color, title_color) = self.border_color();
-        const HELP: [Text<'static>; 7] = [
+        const HELP: [Text<'static>; 9] = [
             Text::Raw(Cow::Borrowed("Commands\n")),
             Text::Raw(Cow::Borrowed("> event.field.<name> <operator>\n")),
+            Text::Raw(Cow::Borrowed("> span.field.<name> <operator>\n")),
+            Text::Raw(Cow::Borrowed("> index <name>\n")),
             Text::Raw(Cow::Borrowed("Operators\n")),
             Text::Raw(Cow::Borrowed("- == \"<string>\"\n")),
             Text::Raw(Cow::Borrowed("- contains \"<string>\"\n")),
@@ -119,10 +121,11 @@ impl QueryView {
         self.rect.set(Some(chunks[0]));
 
         let items: Vec<Text<'_>> = if let Some(filter) = self.filter.as_ref() {
-            filter
-                .modifier
-                .values()
-                .map(|m| Text::raw(format!("{}\n", m)))
+            let event = filter.modifier.values().map(|m| ("event", m));
+            let span = filter.span_modifier.values().map(|m| ("span", m));
+            event
+                .chain(span)
+                .map(|(scope, m)| Text::raw(format!("{}.{}\n", scope, m)))
                 .collect::<Vec<Text<'_>>>()
         } else {
             vec![]
@@ -0,0 +1,104 @@
+use crate::storage::*;
+use crate::ui::format_time;
+
+use tui::backend::CrosstermBackend;
+use tui::layout::Rect;
+use tui::style::{Color, Style};
+use tui::widgets::{Block, Borders, Paragraph, Text, Widget};
+use tui::Frame;
+
+/// Field values of the span the selected event belongs to, see `Store::fields`
+pub struct SpanView {
+    span: Option<InternalId>,
+    title: String,
+    /// One line per field, followed by one per change of its value
+    lines: Vec<String>,
+}
+
+impl SpanView {
+    pub(crate) fn new() -> SpanView {
+        SpanView {
+            span: None,
+            title: String::new(),
+            lines: Vec::new(),
+        }
+    }
+
+    /// The view is only shown, if the selected event belongs to a span
+    pub(crate) fn visible(&self) -> bool {
+        self.span.is_some()
+    }
+
+    /// Rows needed to show all lines, including borders
+    pub(crate) fn height(&self) -> u16 {
+        self.lines.len() as u16 + 2
+    }
+
+    /// Whether `span` isn't the one shown
+    pub(crate) fn wants_update(&self, span: Option<InternalId>) -> bool {
+        self.span != span
+    }
+
+    pub(crate) fn update(&mut self, store: &Store, span: Option<InternalId>) -> bool {
+        let (title, lines) = match span {
+            Some(span) => (title(store, span), lines(store, span)),
+            None => (String::new(), Vec::new()),
+        };
+        let rerender = self.span != span || self.title != title || self.lines != lines;
+        self.span = span;
+        self.title = title;
+        self.lines = lines;
+        rerender
+    }
+
+    pub(crate) fn render_to(&self, f: &mut Frame<CrosstermBackend>, r: Rect) {
+        let lines: Vec<_> = self
+            .lines
+            .iter()
+            .map(|line| Text::raw(format!("{}\n", line)))
+            .collect();
+        Paragraph::new(lines.iter())
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .border_style(Style::default().fg(Color::DarkGray))
+                    .title(&self.title),
+            )
+            .render(f, r);
+    }
+}
+
+fn title(store: &Store, span: InternalId) -> String {
+    let state = match store.state(span) {
+        Some(SpanState::Open) => "open",
+        Some(SpanState::Idle) => "idle",
+        Some(SpanState::Entered) => "entered",
+        Some(SpanState::Closed) => "closed",
+        None => "evicted",
+    };
+    format!(
+        "Span {} ({})",
+        store.span_name(span).unwrap_or("<unknown>"),
+        state
+    )
+}
+
+fn lines(store: &Store, span: InternalId) -> Vec<String> {
+    let fields = match store.fields(span) {
+        Some(fields) if !fields.is_empty() => fields,
+        _ => return vec!["no fields".to_string()],
+    };
+    let mut lines = Vec::new();
+    for (name, value) in fields.iter() {
+        lines.push(format!("{}: {}", name, value));
+        for changes in fields.history(name).windows(2) {
+            lines.push(format!(
+                "  changed from \"{}\" to \"{}\" at {}",
+                changes[0].value,
+                changes[1].value,
+                format_time(changes[1].nano)
+            ));
+        }
+    }
+    lines
+}
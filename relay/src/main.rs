@@ -0,0 +1,91 @@
+//! Merges the message streams of many processes into one
+//!
+//! ```text
+//! relay [--serve <addr>] [--buffer <messages>] [--listen <uri>]... [--accept <addr>]...
+//! ```
+//!
+//! Inbound, the relay connects to `ConsoleForwarder` endpoints (`--listen`)
+//! and accepts processes in push mode (`--accept`).
+//! Each message is tagged with its source and re-served as a single
+//! `ConsoleForwarder` stream on `--serve`, which any number of consoles can attach to.
+//! Consoles receive the most recent `--buffer` messages when they connect.
+use console::collector;
+use console::connection;
+use console::storage::ListenResponse;
+
+use console_subscriber::BackgroundThreadHandle;
+
+use prost::Message;
+
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const DEFAULT_SERVE: &str = "[::1]:50051";
+const DEFAULT_BUFFER: usize = 10_000;
+/// Delay before reconnecting to a `--listen` endpoint
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+const USAGE: &str =
+    "Usage: relay [--serve <addr>] [--buffer <messages>] [--listen <uri>]... [--accept <addr>]...";
+
+fn main() -> Result<(), failure::Error> {
+    let mut serve = DEFAULT_SERVE.to_string();
+    let mut buffer = DEFAULT_BUFFER;
+    let mut listen = vec![];
+    let mut accept = vec![];
+
+    let mut args = std::env::args().skip(1);
+    while let Some(flag) = args.next() {
+        let value = args.next().ok_or_else(|| failure::err_msg(USAGE))?;
+        match flag.as_str() {
+            "--serve" => serve = value,
+            "--buffer" => buffer = value.parse()?,
+            "--listen" => listen.push(value),
+            "--accept" => accept.push(value),
+            _ => failure::bail!(USAGE),
+        }
+    }
+    if listen.is_empty() && accept.is_empty() {
+        failure::bail!(USAGE);
+    }
+
+    // The outbound side is a regular subscriber endpoint, fed by `relay`
+    let handle = BackgroundThreadHandle::new();
+    handle.replay_buffer(buffer);
+
+    for uri in listen {
+        let handle = handle.clone();
+        thread::spawn(move || loop {
+            let (handle, source) = (handle.clone(), uri.clone());
+            let result =
+                connection::listen_with(&uri, move |response| forward(&handle, &source, &response));
+            if let Err(e) = result {
+                eprintln!("relay: {}: {}", uri, e);
+            }
+            thread::sleep(RECONNECT_DELAY);
+        });
+    }
+    for addr in accept {
+        let handle = handle.clone();
+        thread::spawn(move || {
+            collector::accept_with(&addr, move |peer| {
+                let (handle, source) = (handle.clone(), format!("push://{}", peer));
+                Arc::new(move |response: ListenResponse| forward(&handle, &source, &response))
+            })
+        });
+    }
+
+    tokio::run(handle.into_server(&serve));
+    Ok(())
+}
+
+fn forward(handle: &BackgroundThreadHandle, source: &str, response: &ListenResponse) {
+    let mut buf = Vec::with_capacity(response.encoded_len());
+    response
+        .encode(&mut buf)
+        .expect("BUG: Buffer has insufficient capacity");
+    handle
+        .relay(source, &buf)
+        .expect("BUG: Both crates share the same protocol");
+}
@@ -1,6 +1,6 @@
 fn main() {
     tower_grpc_build::Config::new()
-        .enable_client(false)
+        .enable_client(true)
         .enable_server(true)
         .build(&["../proto/tracing.proto"], &["../proto/"])
         .unwrap_or_else(|e| panic!("protobuf compilation failed: {}", e));
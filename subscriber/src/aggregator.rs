@@ -0,0 +1,483 @@
+//! Fan-out of subscriber messages to consoles and recordings
+//!
+//! The aggregator either runs on its own thread, see `BackgroundThreadHandle`,
+//! or as a task on the applications runtime, see `BackgroundTaskHandle`.
+//!
+//! Relays forward messages of other processes through it, optionally
+//! keeping a replay buffer for consoles which connect later.
+//!
+//! It also keeps track of its own overhead, which is periodically
+//! broadcasted as `SubscriberStats`, and optionally samples process resources,
+//! see `BackgroundThreadHandle::sample_process`. Once it relays messages,
+//! its own stats are left out, consoles couldn't tell them from the relayed ones.
+use crate::file::FileSink;
+use crate::messages;
+use crate::messages::listen_response::Variant;
+use crate::process::Sampler;
+use crate::Registry;
+
+use chrono::prelude::*;
+
+use crossbeam::channel;
+
+use futures::sync::mpsc;
+use futures::{future, Async, Future, Poll, Stream};
+
+use prost::Message as _;
+
+use tokio::timer::Delay;
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often `SubscriberStats` are emitted
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+/// Errors kept until they can be reported, older ones are dropped
+const MAX_ERRORS: usize = 16;
+
+/// Messages handled by the aggregator
+pub(crate) enum Message {
+    Variant(Variant),
+    /// Flushes all outputs, acknowledged once done
+    Flush(channel::Sender<()>),
+    /// Starts sampling process resources, `None` stops it
+    SampleProcess(Option<Duration>),
+    /// A message of another process, see `BackgroundThreadHandle::relay`
+    Relayed(messages::ListenResponse),
+    /// Sets the capacity of the replay buffer, `0` disables it
+    ReplayBuffer(usize),
+    /// A problem outside of the aggregator, reported with the next stats
+    Error(String),
+}
+
+/// Sending half of a channel towards the aggregator
+pub(crate) enum AggregatorSender<T> {
+    Thread(channel::Sender<T>),
+    /// Unbounded channels don't expose their length, it's counted alongside
+    Task(mpsc::UnboundedSender<T>, Arc<AtomicUsize>),
+}
+
+// Derive would require `T: Clone`
+impl<T> Clone for AggregatorSender<T> {
+    fn clone(&self) -> Self {
+        match self {
+            AggregatorSender::Thread(tx) => AggregatorSender::Thread(tx.clone()),
+            AggregatorSender::Task(tx, len) => AggregatorSender::Task(tx.clone(), len.clone()),
+        }
+    }
+}
+
+impl<T> AggregatorSender<T> {
+    pub(crate) fn send(&self, item: T) {
+        assert!(self.try_send(item), "BUG: No aggregator available");
+    }
+
+    /// Returns `false` if the aggregator is gone
+    pub(crate) fn try_send(&self, item: T) -> bool {
+        match self {
+            AggregatorSender::Thread(tx) => tx.send(item).is_ok(),
+            AggregatorSender::Task(tx, len) => {
+                len.fetch_add(1, Ordering::SeqCst);
+                let sent = tx.unbounded_send(item).is_ok();
+                if !sent {
+                    len.fetch_sub(1, Ordering::SeqCst);
+                }
+                sent
+            }
+        }
+    }
+}
+
+/// A destination for the aggregated message stream
+pub(crate) enum Output {
+    /// A connected console, optionally requesting pretty debug output
+    Network(
+        mpsc::Sender<messages::ListenResponse>,
+        Option<messages::PrettyDebug>,
+    ),
+    /// A recording, see `BackgroundThreadHandle::record_to_file`
+    File(FileSink),
+}
+
+enum SendResult {
+    Sent,
+    /// The console couldn't keep up
+    Dropped,
+    /// The output is gone and should be removed
+    Closed,
+    /// The output failed and should be removed, the error is reported with the next stats
+    Failed(String),
+}
+
+impl Output {
+    /// If `blocking` is set, waits for lagging consoles to catch up.
+    /// Otherwise, messages to lagging consoles are dropped.
+    fn send(&mut self, response: messages::ListenResponse, blocking: bool) -> SendResult {
+        match self {
+            Output::Network(sender, _) => {
+                if blocking && future::poll_fn(|| sender.poll_ready()).wait().is_err() {
+                    // Connection reset
+                    return SendResult::Closed;
+                }
+                match sender.try_send(response) {
+                    Ok(()) => SendResult::Sent,
+                    Err(ref e) if e.is_full() => SendResult::Dropped,
+                    Err(_) => SendResult::Closed,
+                }
+            }
+            Output::File(file) => match file.write(&response) {
+                Ok(()) => SendResult::Sent,
+                Err(e) => SendResult::Failed(format!("recording stopped: {}", e)),
+            },
+        }
+    }
+
+    fn flush(&mut self) {
+        match self {
+            Output::Network(..) => {}
+            Output::File(file) => {
+                let _ = file.flush();
+            }
+        }
+    }
+}
+
+struct OutputEntry {
+    id: u64,
+    output: Output,
+
+    /// Accumulated send latency since the last stats
+    latency: Duration,
+    sends: u32,
+}
+
+/// Counters for `SubscriberStats`
+struct Stats {
+    /// Events since the last stats
+    events: u64,
+    since: Instant,
+
+    bytes_sent: u64,
+    dropped: u64,
+    /// Not reported yet, see `SubscriberStats::errors`
+    errors: Vec<String>,
+}
+
+impl Stats {
+    fn error(&mut self, error: String) {
+        if self.errors.len() == MAX_ERRORS {
+            self.errors.remove(0);
+        }
+        self.errors.push(error);
+    }
+}
+
+pub(crate) struct Aggregator {
+    outputs: Vec<OutputEntry>,
+    output_counter: u64,
+    /// Only the aggregator thread may block
+    blocking: bool,
+
+    stats: Stats,
+    next_stats: Instant,
+    registry: Arc<RwLock<Registry>>,
+
+    sampler: Option<Sampler>,
+
+    /// Most recent messages, sent to outputs once they are added
+    replay: VecDeque<messages::ListenResponse>,
+    replay_capacity: usize,
+    /// Messages of other processes were relayed, see `Message::Relayed`
+    relaying: bool,
+}
+
+impl Aggregator {
+    fn new(blocking: bool, registry: Arc<RwLock<Registry>>) -> Aggregator {
+        Aggregator {
+            outputs: Vec::new(),
+            output_counter: 0,
+            blocking,
+
+            stats: Stats {
+                events: 0,
+                since: Instant::now(),
+                bytes_sent: 0,
+                dropped: 0,
+                errors: Vec::new(),
+            },
+            next_stats: Instant::now() + STATS_INTERVAL,
+            registry,
+
+            sampler: None,
+
+            replay: VecDeque::new(),
+            replay_capacity: 0,
+            relaying: false,
+        }
+    }
+
+    fn add_output(&mut self, mut output: Output) {
+        // TODO: Track and rebroadcast newspan information for live spans
+        for response in &self.replay {
+            match output.send(response.clone(), self.blocking) {
+                SendResult::Sent => {}
+                SendResult::Dropped => self.stats.dropped += 1,
+                SendResult::Closed => return,
+                SendResult::Failed(error) => return self.stats.error(error),
+            }
+        }
+        self.output_counter += 1;
+        self.outputs.push(OutputEntry {
+            id: self.output_counter,
+            output,
+            latency: Duration::default(),
+            sends: 0,
+        });
+    }
+
+    fn broadcast(&mut self, message: Variant) {
+        self.broadcast_response(messages::ListenResponse {
+            variant: Some(message),
+            source: String::new(),
+        })
+    }
+
+    fn broadcast_response(&mut self, response: messages::ListenResponse) {
+        if let Some(Variant::Event(_)) = response.variant {
+            self.stats.events += 1;
+        }
+        match response.variant {
+            // Outdated by the time it would be replayed
+            Some(Variant::Stats(_)) => {}
+            _ if self.replay_capacity > 0 => {
+                if self.replay.len() == self.replay_capacity {
+                    self.replay.pop_front();
+                }
+                self.replay.push_back(response.clone());
+            }
+            _ => {}
+        }
+        let len = response.encoded_len() as u64;
+
+        let blocking = self.blocking;
+        let mut closed = vec![];
+        for (i, entry) in self.outputs.iter_mut().enumerate() {
+            let start = Instant::now();
+            match entry.output.send(response.clone(), blocking) {
+                SendResult::Sent => self.stats.bytes_sent += len,
+                SendResult::Dropped => self.stats.dropped += 1,
+                // Mark for removal
+                SendResult::Closed => closed.push(i),
+                SendResult::Failed(error) => {
+                    self.stats.error(error);
+                    closed.push(i);
+                }
+            }
+            entry.latency += start.elapsed();
+            entry.sends += 1;
+        }
+        // Traverse in reverse order, to keep index valid during removal
+        for &i in closed.iter().rev() {
+            let _ = self.outputs.remove(i);
+        }
+    }
+
+    fn flush(&mut self) {
+        self.outputs
+            .iter_mut()
+            .for_each(|entry| entry.output.flush());
+    }
+
+    fn handle(&mut self, message: Message) {
+        match message {
+            Message::Variant(variant) => self.broadcast(variant),
+            Message::Flush(ack) => {
+                self.flush();
+                let _ = ack.send(());
+            }
+            Message::SampleProcess(interval) => self.sampler = interval.map(Sampler::new),
+            Message::Relayed(response) => {
+                self.relaying = true;
+                self.broadcast_response(response)
+            }
+            Message::ReplayBuffer(capacity) => {
+                self.replay_capacity = capacity;
+                while self.replay.len() > capacity {
+                    self.replay.pop_front();
+                }
+            }
+            Message::Error(error) => self.stats.error(error),
+        }
+    }
+
+    /// Emits stats and process samples, which are due
+    fn tick(&mut self, queue_depth: usize) {
+        let now = Instant::now();
+        if now >= self.next_stats {
+            self.emit_stats(queue_depth);
+            self.next_stats = now + STATS_INTERVAL;
+        }
+        let sample = self.sampler.as_mut().and_then(|sampler| sampler.poll(now));
+        match sample {
+            Some(Ok(sample)) => self.broadcast(Variant::Process(sample)),
+            Some(Err(e)) => {
+                self.stats.error(format!("process sampling stopped: {}", e));
+                self.sampler = None;
+            }
+            None => {}
+        }
+    }
+
+    /// When `Aggregator::tick` has to be called next
+    fn next_tick(&self) -> Instant {
+        match &self.sampler {
+            Some(sampler) => self.next_stats.min(sampler.next()),
+            None => self.next_stats,
+        }
+    }
+
+    /// Broadcasts `SubscriberStats` and resets the interval counters
+    fn emit_stats(&mut self, queue_depth: usize) {
+        let elapsed = self.stats.since.elapsed();
+        let secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) * 1e-9;
+        let consoles = self
+            .outputs
+            .iter_mut()
+            .filter(|entry| match entry.output {
+                Output::Network(..) => true,
+                Output::File(_) => false,
+            })
+            .map(|entry| {
+                let latency = entry.latency / entry.sends.max(1);
+                entry.latency = Duration::default();
+                entry.sends = 0;
+                messages::ConsoleStats {
+                    id: entry.id,
+                    send_latency_micros: latency.as_micros() as u64,
+                }
+            })
+            .collect();
+        let registry_size = {
+            let registry = try_lock!(self.registry.read());
+            (registry.spans.len() - registry.reusable.len()) as u64
+        };
+
+        let stats = messages::SubscriberStats {
+            timestamp: Some(messages::Timestamp {
+                nano: Utc::now().timestamp_nanos(),
+            }),
+            events_per_sec: self.stats.events as f64 / secs,
+            queue_depth: queue_depth as u64,
+            consoles,
+            bytes_sent: self.stats.bytes_sent,
+            dropped: self.stats.dropped,
+            registry_size,
+            // Kept until there is someone to report them to
+            errors: if self.outputs.is_empty() {
+                Vec::new()
+            } else {
+                std::mem::replace(&mut self.stats.errors, Vec::new())
+            },
+        };
+        self.stats.events = 0;
+        self.stats.since = Instant::now();
+        // Without a source, they would be mixed up with the stats of relayed processes
+        if !self.relaying {
+            self.broadcast(Variant::Stats(stats));
+        }
+    }
+}
+
+pub(crate) fn spawn_thread(
+    messages: channel::Receiver<Message>,
+    outputs: channel::Receiver<Output>,
+    registry: Arc<RwLock<Registry>>,
+) {
+    thread::spawn(move || {
+        let mut aggregator = Aggregator::new(true, registry);
+        loop {
+            aggregator.tick(messages.len());
+            let now = Instant::now();
+            let deadline = aggregator.next_tick();
+            let timeout = if deadline > now {
+                deadline - now
+            } else {
+                Duration::default()
+            };
+            let message = match messages.recv_timeout(timeout) {
+                Ok(message) => message,
+                Err(channel::RecvTimeoutError::Timeout) => continue,
+                Err(channel::RecvTimeoutError::Disconnected) => return,
+            };
+            while let Ok(output) = outputs.try_recv() {
+                aggregator.add_output(output);
+            }
+            aggregator.handle(message);
+            // Don't let recordings lag behind, once we caught up
+            if messages.is_empty() {
+                aggregator.flush();
+            }
+        }
+    });
+}
+
+/// The aggregator as a future, resolves once all subscribers are dropped
+pub(crate) struct AggregatorTask {
+    aggregator: Aggregator,
+    messages: mpsc::UnboundedReceiver<Message>,
+    /// Number of messages waiting, see `AggregatorSender::Task`
+    queue_depth: Arc<AtomicUsize>,
+    outputs: mpsc::UnboundedReceiver<Output>,
+    timer: Delay,
+}
+
+impl AggregatorTask {
+    pub(crate) fn new(
+        messages: mpsc::UnboundedReceiver<Message>,
+        queue_depth: Arc<AtomicUsize>,
+        outputs: mpsc::UnboundedReceiver<Output>,
+        registry: Arc<RwLock<Registry>>,
+    ) -> AggregatorTask {
+        let aggregator = Aggregator::new(false, registry);
+        AggregatorTask {
+            timer: Delay::new(aggregator.next_tick()),
+            aggregator,
+            messages,
+            queue_depth,
+            outputs,
+        }
+    }
+}
+
+impl Future for AggregatorTask {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        self.aggregator
+            .tick(self.queue_depth.load(Ordering::SeqCst));
+        self.timer.reset(self.aggregator.next_tick());
+        // Registers the wakeup, a timer error only delays the next tick
+        let _ = self.timer.poll();
+        loop {
+            while let Async::Ready(Some(output)) = self.outputs.poll()? {
+                self.aggregator.add_output(output);
+            }
+            match self.messages.poll()? {
+                Async::Ready(Some(message)) => {
+                    self.queue_depth.fetch_sub(1, Ordering::SeqCst);
+                    self.aggregator.handle(message)
+                }
+                Async::Ready(None) => return Ok(Async::Ready(())),
+                Async::NotReady => {
+                    // Don't let recordings lag behind, once we caught up
+                    self.aggregator.flush();
+                    return Ok(Async::NotReady);
+                }
+            }
+        }
+    }
+}
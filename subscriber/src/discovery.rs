@@ -0,0 +1,126 @@
+//! Local discovery of instrumented processes
+//!
+//! Discoverable subscribers bind an ephemeral port and announce it with a file
+//! in `$XDG_RUNTIME_DIR/tracing-console/`, named after the process id and a
+//! counter, a process might run several servers.
+//! The file contains a length-delimited `ProcessInfo` message.
+//!
+//! Without `XDG_RUNTIME_DIR`, a per-user directory in the temporary directory is used.
+//! It's only accessible by its owner, otherwise other users could read or plant announcements.
+//!
+//! The file is removed once the returned `Discovery` is dropped. Files of processes
+//! which didn't get the chance to, are ignored and cleaned up by the console.
+use crate::messages;
+
+use chrono::prelude::*;
+
+use prost::Message;
+
+use std::env;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Number of servers announced by this process so far
+static SERVERS: AtomicUsize = AtomicUsize::new(0);
+
+/// `$XDG_RUNTIME_DIR/tracing-console`, falls back to a per-user temporary directory
+pub(crate) fn directory() -> PathBuf {
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("tracing-console"),
+        None => fallback_directory(),
+    }
+}
+
+#[cfg(unix)]
+fn fallback_directory() -> PathBuf {
+    // Safe: `getuid` has no preconditions and always succeeds
+    let uid = unsafe { libc::getuid() };
+    env::temp_dir().join(format!("tracing-console-{}", uid))
+}
+
+#[cfg(not(unix))]
+fn fallback_directory() -> PathBuf {
+    // The temporary directory is per user already
+    env::temp_dir().join("tracing-console")
+}
+
+/// Creates `dir` if needed, fails unless only the current user can access it
+#[cfg(unix)]
+fn create_private(dir: &Path) -> io::Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)?;
+    // It might have been created by someone else upfront
+    let metadata = fs::symlink_metadata(dir)?;
+    // Safe: `getuid` has no preconditions and always succeeds
+    let uid = unsafe { libc::getuid() };
+    if !metadata.is_dir() || metadata.uid() != uid || metadata.mode() & 0o077 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} is accessible by other users", dir.display()),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn create_private(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)
+}
+
+/// Announces a discoverable server, see `BackgroundThreadHandle::run_discoverable`
+///
+/// The announcement is removed on drop. Exiting the process skips destructors
+/// of values owned by other threads, so keep it on the main thread, e.g. until `main` returns.
+#[must_use = "the announcement is removed once dropped"]
+pub struct Discovery {
+    path: PathBuf,
+}
+
+impl Discovery {
+    pub(crate) fn register(addr: SocketAddr) -> io::Result<Discovery> {
+        let dir = directory();
+        create_private(&dir)?;
+
+        let exe = env::current_exe()
+            .ok()
+            .and_then(|exe| Some(exe.file_name()?.to_string_lossy().into_owned()))
+            .unwrap_or_default();
+        let info = messages::ProcessInfo {
+            pid: process::id(),
+            exe,
+            addr: format!("http://{}", addr),
+            started: Some(messages::Timestamp {
+                nano: Utc::now().timestamp_nanos(),
+            }),
+        };
+        let mut buf = Vec::new();
+        info.encode_length_delimited(&mut buf)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+
+        // Write and rename, consoles must never see partial files
+        let name = format!(
+            "{}-{}",
+            process::id(),
+            SERVERS.fetch_add(1, Ordering::SeqCst)
+        );
+        let path = dir.join(&name);
+        let tmp = dir.join(format!(".{}.tmp", name));
+        fs::write(&tmp, &buf)?;
+        fs::rename(&tmp, &path)?;
+        Ok(Discovery { path })
+    }
+}
+
+impl Drop for Discovery {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
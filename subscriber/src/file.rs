@@ -0,0 +1,186 @@
+//! Recording of the message stream into local files
+//!
+//! A recording is made up of one or more segments.
+//! Each segment starts with `MAGIC`, followed by a length-delimited
+//! `FileHeader` and the length-delimited `ListenResponse` messages.
+//!
+//! Once a segment exceeds `Rotation::max_bytes`, it is renamed to `<path>.1`,
+//! older segments are shifted (`<path>.1` -> `<path>.2`, ...)
+//! and a new segment is started at `<path>`.
+//! At most `Rotation::max_files` segments are kept around.
+//!
+//! Spans outlive segments, so each new segment repeats the `NewSpan` of the
+//! spans which are still open, right after the header, see `FileHeader::live_spans`.
+
+use crate::messages;
+use crate::messages::listen_response::Variant;
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::prelude::*;
+
+use prost::Message;
+
+pub(crate) const MAGIC: &[u8; 8] = b"TCONSOLE";
+pub(crate) const VERSION: u32 = 1;
+
+/// Size based rotation of recording files
+#[derive(Clone, Debug)]
+pub struct Rotation {
+    /// Start a new segment once the current one exceeds this size
+    pub max_bytes: u64,
+    /// The maximum number of segments, including the current one
+    pub max_files: usize,
+}
+
+impl Default for Rotation {
+    fn default() -> Rotation {
+        Rotation {
+            max_bytes: 64 * 1024 * 1024,
+            max_files: 4,
+        }
+    }
+}
+
+pub(crate) struct FileSink {
+    path: PathBuf,
+    rotation: Rotation,
+
+    file: BufWriter<File>,
+    written: u64,
+    segment: u32,
+    /// Reused encoding buffer
+    buf: Vec<u8>,
+
+    /// `NewSpan` of each open span by source and id, along with the order they arrived in
+    live: HashMap<(String, u64), (u64, messages::ListenResponse)>,
+    live_counter: u64,
+}
+
+impl FileSink {
+    pub(crate) fn create(path: PathBuf, rotation: Rotation) -> io::Result<FileSink> {
+        // Stale segments of a previous recording would be mixed into this one
+        for n in 1..rotation.max_files {
+            let _ = fs::remove_file(rotated_path(&path, n));
+        }
+        let (file, written) = open_segment(&path, 0, &[])?;
+        Ok(FileSink {
+            path,
+            rotation,
+            file,
+            written,
+            segment: 0,
+            buf: Vec::new(),
+            live: HashMap::new(),
+            live_counter: 0,
+        })
+    }
+
+    pub(crate) fn write(&mut self, response: &messages::ListenResponse) -> io::Result<()> {
+        self.buf.clear();
+        response
+            .encode_length_delimited(&mut self.buf)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        self.file.write_all(&self.buf)?;
+        self.written += self.buf.len() as u64;
+        self.track(response);
+
+        if self.written >= self.rotation.max_bytes {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+
+    /// Keeps track of open spans, see `FileHeader::live_spans`
+    fn track(&mut self, response: &messages::ListenResponse) {
+        match &response.variant {
+            Some(Variant::NewSpan(new_span)) => {
+                if let Some(span) = &new_span.span {
+                    // A reused id replaces the closed span
+                    self.live_counter += 1;
+                    self.live.insert(
+                        (response.source.clone(), span.id),
+                        (self.live_counter, response.clone()),
+                    );
+                }
+            }
+            Some(Variant::Close(close)) => {
+                if let Some(span) = &close.span {
+                    self.live.remove(&(response.source.clone(), span.id));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        let max_files = self.rotation.max_files;
+        if max_files > 1 {
+            // Shift older segments, the oldest one falls off
+            let _ = fs::remove_file(rotated_path(&self.path, max_files - 1));
+            for n in (1..max_files - 1).rev() {
+                let from = rotated_path(&self.path, n);
+                if from.exists() {
+                    fs::rename(from, rotated_path(&self.path, n + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        }
+
+        self.segment += 1;
+        // Parents arrived before their children
+        let mut live: Vec<_> = self.live.values().collect();
+        live.sort_by_key(|(order, _)| *order);
+        let live: Vec<_> = live.into_iter().map(|(_, response)| response).collect();
+        let (file, written) = open_segment(&self.path, self.segment, &live)?;
+        self.file = file;
+        self.written = written;
+        Ok(())
+    }
+}
+
+/// `<path>.<n>`
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+/// Creates a new segment and writes magic, header and the `NewSpan` of `live` spans
+///
+/// Returns the writer and the amount of bytes written
+fn open_segment(
+    path: &Path,
+    segment: u32,
+    live: &[&messages::ListenResponse],
+) -> io::Result<(BufWriter<File>, u64)> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let header = messages::FileHeader {
+        version: VERSION,
+        created: Some(messages::Timestamp {
+            nano: Utc::now().timestamp_nanos(),
+        }),
+        segment,
+        live_spans: live.len() as u32,
+    };
+    let mut buf = MAGIC.to_vec();
+    header
+        .encode_length_delimited(&mut buf)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    for response in live {
+        response
+            .encode_length_delimited(&mut buf)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    }
+    file.write_all(&buf)?;
+    Ok((file, buf.len() as u64))
+}
@@ -1,6 +1,8 @@
 //! A remote endpoint for `tracing-console`
 //!
 //! The subscriber currently spawns two threads two manage the endpoint.
+//! Applications which already run on tokio can use `BackgroundTaskHandle` instead,
+//! which runs the aggregator and the server as tasks on their runtime.
 //!
 //! When an application interacts with `tracing`, under the hood,
 //! the current subscriber is called.
@@ -14,7 +16,11 @@
 //! # Network
 //! The following information will not be send to the console, but tracked locally:
 //!  - `span.enter()/exit()`, tracked via Thread-Local-Storage.
+//!    Only exits on another thread than the enter involve a mutex access.
 //!  - `span.clone()/` and dropping, currently involves a mutex access
+//!
+//! Once a span is dropped, a `SpanClose` summarizes its enters: the first one
+//! and the total busy time.
 //!  
 //! # Thread overview:
 //!
@@ -50,6 +56,39 @@
 //! handle.run_background("[::1]:50051").join().unwrap();
 //! # }
 //! ```
+//!
+//! # `log` records
+//!
+//! With the `log` feature enabled, `BackgroundThreadHandle::new_logger` provides
+//! a `log::Log` implementation. Records of dependencies which still use `log`
+//! are then forwarded as events, next to the `tracing` ones.
+//!
+//! # Push mode
+//!
+//! If consoles can't reach the process, e.g. in containers or behind NAT,
+//! the process can connect out instead. `BackgroundThreadHandle::run_push`
+//! streams to a console started with `console --accept <addr>`.
+//!
+//! # Integration tests
+//!
+//! `BackgroundThreadHandle::loopback` attaches a console within the same process.
+//! Together with `console::loopback`, tests can assert on the emitted spans
+//! and events, instead of matching on log output.
+//!
+//! # Recording
+//!
+//! When no console can be attached, e.g. in CI runs, the message stream
+//! can be written to a file instead, or in addition to serving it.
+//! The console opens such a recording with `console --file <path>`.
+//!
+//! ```rust,ignore
+//! use console_subscriber::{BackgroundThreadHandle, Rotation};
+//!
+//! let handle = BackgroundThreadHandle::new();
+//! handle
+//!     .record_to_file("trace.console", Rotation::default())
+//!     .expect("Couldn't create recording");
+//! ```
 
 // Borrowed from `tracing`
 
@@ -67,7 +106,16 @@ macro_rules! try_lock {
     };
 }
 
+mod aggregator;
+mod discovery;
+mod file;
+#[cfg(feature = "log")]
+mod logger;
+mod loopback;
 mod messages;
+mod process;
+mod push;
+mod redaction;
 mod server;
 mod subscriber;
 
@@ -75,8 +123,14 @@ use tracing_core::span;
 
 use std::collections::HashMap;
 use std::num::NonZeroU64;
-use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::{AtomicI64, AtomicU64, AtomicUsize};
 
+pub use discovery::Discovery;
+pub use file::Rotation;
+#[cfg(feature = "log")]
+pub use logger::ConsoleLogger;
+pub use loopback::Loopback;
+pub use redaction::{FieldMatch, Redaction, Replacement};
 pub use server::*;
 
 #[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Ord, Eq, Hash)]
@@ -92,9 +146,17 @@ impl From<ThreadId> for messages::ThreadId {
 pub struct Span {
     refcount: AtomicUsize,
     follows: Vec<SpanId>,
+    /// Explicit or contextual parent, `None` for root spans
+    parent: Option<SpanId>,
+    /// Target of the span, see `Redaction::target`
+    target: &'static str,
+    /// Timestamp of the first enter, `0` if it was never entered
+    first_entered: AtomicI64,
+    /// Summed up time between enter and exit, reported by `SpanClose`
+    busy_nanos: AtomicU64,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct SpanId(NonZeroU64);
 
 impl SpanId {
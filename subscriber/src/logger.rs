@@ -0,0 +1,96 @@
+//! Forwarding of `log` records, enabled by the `log` feature
+use crate::messages;
+use crate::messages::listen_response::Variant;
+use crate::messages::Recorder;
+use crate::subscriber::ConsoleForwarder;
+use crate::SpanId;
+
+use chrono::prelude::*;
+
+/// A `log::Log` implementation, which forwards records as `Event` messages
+///
+/// Records show up in the same stream as `tracing` events,
+/// their metadata is marked with `from_log`.
+///
+/// ```rust,ignore
+/// let handle = BackgroundThreadHandle::new();
+/// handle
+///     .new_logger(log::LevelFilter::Info)
+///     .init()
+///     .expect("Another logger is already installed");
+/// ```
+pub struct ConsoleLogger {
+    forwarder: ConsoleForwarder,
+    level: log::LevelFilter,
+}
+
+impl ConsoleLogger {
+    pub(crate) fn new(forwarder: ConsoleForwarder, level: log::LevelFilter) -> ConsoleLogger {
+        ConsoleLogger { forwarder, level }
+    }
+
+    /// Installs this logger as the global `log` logger
+    pub fn init(self) -> Result<(), log::SetLoggerError> {
+        log::set_max_level(self.level);
+        log::set_boxed_logger(Box::new(self))
+    }
+}
+
+impl log::Log for ConsoleLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let message = messages::Field {
+            name: "message".to_string(),
+        };
+        let level = match record.level() {
+            log::Level::Error => messages::Level::Error,
+            log::Level::Warn => messages::Level::Warn,
+            log::Level::Info => messages::Level::Info,
+            log::Level::Debug => messages::Level::Debug,
+            log::Level::Trace => messages::Level::Trace,
+        };
+        let metadata = messages::Metadata {
+            fieldset: vec![message.clone()],
+            level: level.into(),
+            name: "log event".to_string(),
+            target: record.target().to_string(),
+            module_path: record.module_path().unwrap_or_default().to_string(),
+            file: record.file().unwrap_or_default().to_string(),
+            line: record.line().map(|num| messages::LineNum { num }),
+            is_event: true,
+            is_span: false,
+            from_log: true,
+            // `log` has no notion of callsites
+            callsite: 0,
+            kind: messages::Kind::Event.into(),
+        };
+        let mut recorder = Recorder::new(&self.forwarder.formatting, record.target());
+        recorder.record_str_by_name(&message.name, &record.args().to_string());
+        let values = recorder.values;
+        let parent = self.forwarder.current_span();
+        self.forwarder.send(Variant::Event(messages::Event {
+            span: parent.as_ref().map(SpanId::as_message),
+            ancestors: self.forwarder.ancestors(parent),
+            values,
+            fields: vec![message],
+            attributes: Some(messages::Attributes {
+                metadata: Some(metadata),
+                is_root: false,
+                is_contextual: true,
+                parent: None,
+            }),
+            thread: Some(self.forwarder.thread_id().into()),
+            timestamp: Some(messages::Timestamp {
+                nano: Utc::now().timestamp_nanos(),
+            }),
+        }));
+    }
+
+    fn flush(&self) {}
+}
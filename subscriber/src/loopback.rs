@@ -0,0 +1,39 @@
+//! In-process consoles, which don't need a socket
+//!
+//! See `BackgroundThreadHandle::loopback`.
+use crate::messages;
+
+use futures::stream::Wait;
+use futures::sync::mpsc;
+use futures::Stream;
+
+use prost::Message;
+
+/// The message stream of an in-process console
+///
+/// Each item is an encoded `ListenResponse`, exactly as it would be sent via gRPC.
+/// The stream ends once all subscribers and handles are dropped.
+pub struct Loopback {
+    responses: Wait<mpsc::Receiver<messages::ListenResponse>>,
+}
+
+impl Loopback {
+    pub(crate) fn new(responses: mpsc::Receiver<messages::ListenResponse>) -> Loopback {
+        Loopback {
+            responses: responses.wait(),
+        }
+    }
+}
+
+impl Iterator for Loopback {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        let response = self.responses.next()?.ok()?;
+        let mut buf = Vec::with_capacity(response.encoded_len());
+        response
+            .encode(&mut buf)
+            .expect("BUG: Buffer has insufficient capacity");
+        Some(buf)
+    }
+}
@@ -1,57 +1,234 @@
+use crate::redaction::{Redaction, Redactions};
+
 use tracing_core::field::Visit;
 use tracing_core::span;
 
-use std::fmt::Debug;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::{self, Debug, Write};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, PoisonError, RwLock};
 
-include!(concat!(env!("OUT_DIR"), "/tracing.rs"));
+pub use self::generated::*;
 
-#[derive(Default)]
-pub struct Recorder(pub Vec<Value>);
+// Only the `ConsoleCollector` client and the `ConsoleForwarder` server are used
+#[allow(dead_code)]
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/tracing.rs"));
+}
 
-impl Visit for Recorder {
-    fn record_debug(&mut self, field: &tracing_core::Field, value: &dyn Debug) {
-        self.0.push(Value {
+/// Controls how `Recorder` formats and redacts values
+///
+/// Shared by all `ConsoleForwarder`s of a handle, can be changed at runtime.
+#[derive(Debug, Default)]
+pub(crate) struct Formatting {
+    /// Number of connected consoles which requested `DebugRecord::pretty`
+    pretty: AtomicUsize,
+    /// Maximum length of a formatted value in bytes, `0` means unlimited
+    max_len: AtomicUsize,
+    /// Whether any redaction rule was added, spares the lock in the common case
+    has_redactions: AtomicBool,
+    /// Replaced on changes, so recorders can keep a snapshot without holding the lock
+    redactions: RwLock<Arc<Redactions>>,
+}
+
+impl Formatting {
+    pub(crate) fn set_max_len(&self, max_len: Option<usize>) {
+        self.max_len.store(max_len.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    pub(crate) fn redact(&self, rule: Redaction) {
+        // The lock only guards an `Arc`, which is never left half updated
+        let mut redactions = self
+            .redactions
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        Arc::make_mut(&mut redactions).push(rule);
+        self.has_redactions.store(true, Ordering::SeqCst);
+    }
+
+    /// The current rules, `None` if there are none
+    fn redactions(&self) -> Option<Arc<Redactions>> {
+        if !self.has_redactions.load(Ordering::SeqCst) {
+            return None;
+        }
+        let redactions = self
+            .redactions
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        Some(redactions.clone())
+    }
+}
+
+/// Keeps `DebugRecord::pretty` enabled while alive
+pub(crate) struct PrettyDebug(Arc<Formatting>);
+
+impl PrettyDebug {
+    pub(crate) fn new(formatting: Arc<Formatting>) -> PrettyDebug {
+        formatting.pretty.fetch_add(1, Ordering::Relaxed);
+        PrettyDebug(formatting)
+    }
+}
+
+impl Drop for PrettyDebug {
+    fn drop(&mut self) {
+        self.0.pretty.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A `fmt::Write` which stops storing output after `max_len` bytes
+///
+/// Formatting continues, to count the omitted bytes, but nothing is allocated for them.
+struct Truncating {
+    buf: String,
+    max_len: usize,
+    omitted: usize,
+    /// Once set, later writes are omitted, even if they would fit
+    truncated: bool,
+}
+
+impl Truncating {
+    fn format(value: &dyn Debug, pretty: bool, max_len: usize) -> String {
+        let mut writer = Truncating {
+            buf: String::new(),
+            max_len,
+            omitted: 0,
+            truncated: false,
+        };
+        let _ = if pretty {
+            write!(writer, "{:#?}", value)
+        } else {
+            write!(writer, "{:?}", value)
+        };
+        if writer.omitted > 0 {
+            let _ = write!(
+                writer.buf,
+                "... (truncated, {} bytes omitted)",
+                writer.omitted
+            );
+        }
+        writer.buf
+    }
+}
+
+impl Write for Truncating {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.max_len == 0 {
+            self.buf.push_str(s);
+            return Ok(());
+        }
+        if self.truncated {
+            // Storing it would leave a gap in the value
+            self.omitted += s.len();
+            return Ok(());
+        }
+        let remaining = self.max_len.saturating_sub(self.buf.len());
+        if s.len() <= remaining {
+            self.buf.push_str(s);
+        } else {
+            let mut end = remaining;
+            while !s.is_char_boundary(end) {
+                end -= 1;
+            }
+            self.buf.push_str(&s[..end]);
+            self.omitted += s.len() - end;
+            self.truncated = true;
+        }
+        Ok(())
+    }
+}
+
+/// Converts the fields of a span or event into `Value`s
+pub struct Recorder<'a> {
+    pub values: Vec<Value>,
+    formatting: &'a Formatting,
+    /// Target of the span or event, which redaction rules can be scoped to
+    target: &'a str,
+    /// Snapshot of the rules, values are formatted without holding any lock
+    redactions: Option<Arc<Redactions>>,
+}
+
+impl<'a> Recorder<'a> {
+    pub(crate) fn new(formatting: &'a Formatting, target: &'a str) -> Recorder<'a> {
+        Recorder {
+            values: Vec::new(),
+            formatting,
+            target,
+            redactions: formatting.redactions(),
+        }
+    }
+
+    /// Records a field, which isn't part of the metadata, e.g. a `log` message
+    pub(crate) fn record_str_by_name(&mut self, name: &str, value: &str) {
+        if !self.redacted(name, &value) {
+            self.push(name, value::Value::Str(value.to_string()));
+        }
+    }
+
+    /// Pushes the replacement instead, if a redaction rule matches the field
+    fn redacted(&mut self, name: &str, value: &dyn Debug) -> bool {
+        let redactions = match self.redactions {
+            Some(ref redactions) => redactions,
+            None => return false,
+        };
+        match redactions.find(self.target, name) {
+            Some(replacement) => {
+                let replaced = redactions.replace(replacement, value);
+                self.push(name, value::Value::Str(replaced));
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn push(&mut self, name: &str, value: value::Value) {
+        self.values.push(Value {
             field: Some(Field {
-                name: field.name().to_string(),
+                name: name.to_string(),
             }),
-            value: Some(value::Value::Debug(DebugRecord {
-                debug: format!("{:?}", value),
-                pretty: format!("{:#?}", value),
-            })),
+            value: Some(value),
         })
     }
+}
 
-    fn record_i64(&mut self, field: &tracing_core::Field, value: i64) {
-        self.0.push(Value {
-            field: Some(Field {
-                name: field.name().to_string(),
+impl<'a> Visit for Recorder<'a> {
+    fn record_debug(&mut self, field: &tracing_core::Field, value: &dyn Debug) {
+        if self.redacted(field.name(), value) {
+            return;
+        }
+        let max_len = self.formatting.max_len.load(Ordering::Relaxed);
+        // Formatting is expensive, only do it if anyone looks at the result
+        let pretty = if self.formatting.pretty.load(Ordering::Relaxed) > 0 {
+            Truncating::format(value, true, max_len)
+        } else {
+            String::new()
+        };
+        self.push(
+            field.name(),
+            value::Value::Debug(DebugRecord {
+                debug: Truncating::format(value, false, max_len),
+                pretty,
             }),
-            value: Some(value::Value::Signed(value)),
-        })
+        )
+    }
+
+    fn record_i64(&mut self, field: &tracing_core::Field, value: i64) {
+        if !self.redacted(field.name(), &value) {
+            self.push(field.name(), value::Value::Signed(value))
+        }
     }
     fn record_u64(&mut self, field: &tracing_core::Field, value: u64) {
-        self.0.push(Value {
-            field: Some(Field {
-                name: field.name().to_string(),
-            }),
-            value: Some(value::Value::Unsigned(value)),
-        })
+        if !self.redacted(field.name(), &value) {
+            self.push(field.name(), value::Value::Unsigned(value))
+        }
     }
     fn record_bool(&mut self, field: &tracing_core::Field, value: bool) {
-        self.0.push(Value {
-            field: Some(Field {
-                name: field.name().to_string(),
-            }),
-            value: Some(value::Value::Boolean(value)),
-        })
+        if !self.redacted(field.name(), &value) {
+            self.push(field.name(), value::Value::Boolean(value))
+        }
     }
     fn record_str(&mut self, field: &tracing_core::Field, value: &str) {
-        self.0.push(Value {
-            field: Some(Field {
-                name: field.name().to_string(),
-            }),
-            value: Some(value::Value::Str(value.to_string())),
-        })
+        self.record_str_by_name(field.name(), value)
     }
 }
 
@@ -80,16 +257,30 @@ impl From<&'static tracing_core::Metadata<'static>> for Metadata {
         }
         .into();
 
+        let kind = if meta.is_span() {
+            Kind::Span
+        } else {
+            Kind::Event
+        }
+        .into();
+
+        // `Identifier` hashes the callsite address, which is unique within the process
+        let mut hasher = DefaultHasher::new();
+        meta.callsite().hash(&mut hasher);
+
         Metadata {
             fieldset,
             level,
             name: meta.name().to_string(),
-            target: meta.name().to_string(),
-            module_path: meta.name().to_string(),
-            file: meta.name().to_string(),
+            target: meta.target().to_string(),
+            module_path: meta.module_path().unwrap_or_default().to_string(),
+            file: meta.file().unwrap_or_default().to_string(),
             line: meta.line().map(|num| LineNum { num }),
             is_event: meta.is_event(),
             is_span: meta.is_span(),
+            from_log: false,
+            callsite: hasher.finish(),
+            kind,
         }
     }
 }
@@ -104,3 +295,171 @@ impl<'a> From<&'a span::Attributes<'a>> for Attributes {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::redaction::{FieldMatch, Replacement};
+
+    use prost::Message;
+
+    use regex::Regex;
+
+    use tracing_core::callsite::Callsite;
+    use tracing_core::metadata::Kind as MetaKind;
+    use tracing_core::{metadata, Interest};
+
+    struct TestCallsite(&'static tracing_core::Metadata<'static>);
+
+    impl Callsite for TestCallsite {
+        fn set_interest(&self, _: Interest) {}
+        fn metadata(&self) -> &tracing_core::Metadata<'_> {
+            self.0
+        }
+    }
+
+    static EVENT_CALLSITE: TestCallsite = TestCallsite(&EVENT_META);
+    static EVENT_META: tracing_core::Metadata<'static> = metadata! {
+        name: "event",
+        target: "console::target",
+        level: tracing_core::Level::WARN,
+        fields: &["message", "foo"],
+        callsite: &EVENT_CALLSITE,
+        kind: MetaKind::EVENT,
+    };
+
+    static SPAN_CALLSITE: TestCallsite = TestCallsite(&SPAN_META);
+    static SPAN_META: tracing_core::Metadata<'static> = metadata! {
+        name: "span",
+        target: "console::other",
+        level: tracing_core::Level::TRACE,
+        fields: &[],
+        callsite: &SPAN_CALLSITE,
+        kind: MetaKind::SPAN,
+    };
+
+    #[test]
+    fn metadata_conversion() {
+        let meta = Metadata::from(&EVENT_META);
+
+        assert_eq!(meta.name, "event");
+        assert_eq!(meta.target, "console::target");
+        assert_eq!(meta.module_path, module_path!());
+        assert_eq!(meta.file, file!());
+        assert_eq!(meta.line, EVENT_META.line().map(|num| LineNum { num }));
+        assert_eq!(Level::from_i32(meta.level), Some(Level::Warn));
+        assert_eq!(Kind::from_i32(meta.kind), Some(Kind::Event));
+        assert!(meta.is_event && !meta.is_span && !meta.from_log);
+        let fields: Vec<_> = meta.fieldset.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(fields, ["message", "foo"]);
+    }
+
+    #[test]
+    fn metadata_kind_and_callsite() {
+        let event = Metadata::from(&EVENT_META);
+        let span = Metadata::from(&SPAN_META);
+
+        assert_eq!(Kind::from_i32(span.kind), Some(Kind::Span));
+        assert!(span.is_span && !span.is_event);
+        assert_eq!(Level::from_i32(span.level), Some(Level::Trace));
+
+        assert_ne!(event.callsite, span.callsite);
+        // Stable for the same callsite
+        assert_eq!(event.callsite, Metadata::from(&EVENT_META).callsite);
+    }
+
+    #[test]
+    fn metadata_round_trip() {
+        for meta in &[&EVENT_META, &SPAN_META] {
+            let meta = Metadata::from(*meta);
+            let mut buf = Vec::new();
+            meta.encode(&mut buf).unwrap();
+            assert_eq!(Metadata::decode(buf).unwrap(), meta);
+        }
+    }
+
+    /// Records `message` and `foo` of `EVENT_META`
+    fn record(formatting: &Formatting, target: &str, foo: &str) -> Vec<Value> {
+        let fields = EVENT_META.fields();
+        let mut recorder = Recorder::new(formatting, target);
+        recorder.record_str(&fields.field("message").unwrap(), "hello");
+        recorder.record_debug(&fields.field("foo").unwrap(), &foo);
+        recorder.values
+    }
+
+    fn str_value(value: &Value) -> &str {
+        match &value.value {
+            Some(value::Value::Str(string)) => string,
+            Some(value::Value::Debug(debug)) => &debug.debug,
+            other => panic!("unexpected value {:?}", other),
+        }
+    }
+
+    #[test]
+    fn redaction_by_name() {
+        let formatting = Formatting::default();
+        formatting.redact(Redaction {
+            field: FieldMatch::Name("foo".to_string()),
+            target: None,
+            replacement: Replacement::Placeholder,
+        });
+
+        let values = record(&formatting, "console::target", "secret");
+        assert_eq!(str_value(&values[0]), "hello");
+        assert_eq!(str_value(&values[1]), "<redacted>");
+    }
+
+    #[test]
+    fn redaction_hash() {
+        let formatting = Formatting::default();
+        formatting.redact(Redaction {
+            field: FieldMatch::Regex(Regex::new("^f").unwrap()),
+            target: None,
+            replacement: Replacement::Hash,
+        });
+
+        let first = record(&formatting, "console::target", "secret");
+        let second = record(&formatting, "console::target", "secret");
+        let other = record(&formatting, "console::target", "other");
+        assert!(str_value(&first[1]).starts_with("<redacted:"));
+        assert!(!str_value(&first[1]).contains("secret"));
+        assert_eq!(str_value(&first[1]), str_value(&second[1]));
+        assert_ne!(str_value(&first[1]), str_value(&other[1]));
+    }
+
+    #[test]
+    fn redaction_scoped_by_target() {
+        let formatting = Formatting::default();
+        formatting.redact(Redaction {
+            field: FieldMatch::Name("foo".to_string()),
+            target: Some("console::target".to_string()),
+            replacement: Replacement::Placeholder,
+        });
+
+        let redacted =
+            |target: &str| str_value(&record(&formatting, target, "secret")[1]) == "<redacted>";
+        assert!(redacted("console::target"));
+        assert!(redacted("console::target::inner"));
+        assert!(!redacted("console::targets"));
+        assert!(!redacted("console::other"));
+    }
+
+    /// Writes its parts separately, like derived `Debug` impls do
+    struct Parts(&'static [&'static str]);
+
+    impl Debug for Parts {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            self.0.iter().try_for_each(|part| f.write_str(part))
+        }
+    }
+
+    #[test]
+    fn truncation_omits_later_writes() {
+        // The second part is cut before the multi-byte char, the third would still fit
+        let value = Truncating::format(&Parts(&["abcd", "éé", "x"]), false, 5);
+        assert_eq!(value, "abcd... (truncated, 5 bytes omitted)");
+        let value = Truncating::format(&Parts(&["abcd", "e"]), false, 5);
+        assert_eq!(value, "abcde");
+    }
+}
@@ -0,0 +1,137 @@
+//! Resource usage sampling of the current process
+//!
+//! Only supported on Linux, where the data is read from `/proc/self`.
+use crate::messages;
+
+use chrono::prelude::*;
+
+use std::io;
+use std::time::{Duration, Instant};
+
+pub(crate) struct Sampler {
+    interval: Duration,
+    next: Instant,
+    /// Time and cpu time of the previous sample, to calculate the usage
+    last: Option<(Instant, Duration)>,
+}
+
+impl Sampler {
+    pub(crate) fn new(interval: Duration) -> Sampler {
+        Sampler {
+            interval,
+            next: Instant::now(),
+            last: None,
+        }
+    }
+
+    /// When the next sample is due
+    pub(crate) fn next(&self) -> Instant {
+        self.next
+    }
+
+    /// Takes a sample, if one is due
+    pub(crate) fn poll(&mut self, now: Instant) -> Option<io::Result<messages::ProcessSample>> {
+        if now < self.next {
+            return None;
+        }
+        self.next = now + self.interval;
+        Some(self.sample(now))
+    }
+
+    fn sample(&mut self, now: Instant) -> io::Result<messages::ProcessSample> {
+        let usage = imp::read()?;
+        let cpu_usage = match self.last {
+            Some((last, last_cpu)) if now > last => {
+                let cpu = usage.cpu_time.checked_sub(last_cpu).unwrap_or_default();
+                as_secs_f64(cpu) / as_secs_f64(now - last)
+            }
+            _ => 0.0,
+        };
+        self.last = Some((now, usage.cpu_time));
+
+        Ok(messages::ProcessSample {
+            timestamp: Some(messages::Timestamp {
+                nano: Utc::now().timestamp_nanos(),
+            }),
+            cpu_usage,
+            cpu_time_nanos: usage.cpu_time.as_nanos() as u64,
+            rss_bytes: usage.rss_bytes,
+            open_fds: usage.open_fds,
+            threads: usage.threads,
+        })
+    }
+}
+
+fn as_secs_f64(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) * 1e-9
+}
+
+struct Usage {
+    cpu_time: Duration,
+    rss_bytes: u64,
+    open_fds: u64,
+    threads: u64,
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use super::Usage;
+
+    use std::fs;
+    use std::io;
+    use std::time::Duration;
+
+    pub(super) fn read() -> io::Result<Usage> {
+        let stat = fs::read_to_string("/proc/self/stat")?;
+        // The command name might contain whitespace and parens, skip past it
+        let fields: Vec<&str> = stat
+            .rsplitn(2, ')')
+            .next()
+            .unwrap_or_default()
+            .split_whitespace()
+            .collect();
+        // Indices are offset by the 2 skipped fields `pid` and `comm`, see `man 5 proc`
+        let field = |n: usize| -> io::Result<u64> {
+            fields
+                .get(n - 3)
+                .and_then(|field| field.parse().ok())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "/proc/self/stat"))
+        };
+        let ticks = field(14)? + field(15)?;
+        let threads = field(20)?;
+        let rss_pages = field(24)?;
+
+        // Safe: `sysconf` has no preconditions
+        let (ticks_per_sec, page_size) = unsafe {
+            (
+                libc::sysconf(libc::_SC_CLK_TCK) as u64,
+                libc::sysconf(libc::_SC_PAGESIZE) as u64,
+            )
+        };
+        let cpu_time = Duration::from_nanos(ticks * 1_000_000_000 / ticks_per_sec.max(1));
+
+        // Listing the directory takes a descriptor itself, it's not counted
+        let open_fds = (fs::read_dir("/proc/self/fd")?.count() as u64).saturating_sub(1);
+
+        Ok(Usage {
+            cpu_time,
+            rss_bytes: rss_pages * page_size,
+            open_fds,
+            threads,
+        })
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use super::Usage;
+
+    use std::io;
+
+    pub(super) fn read() -> io::Result<Usage> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "process sampling is only supported on linux",
+        ))
+    }
+}
@@ -0,0 +1,126 @@
+//! Push mode, the subscriber connects to a console or collector
+//!
+//! Useful if consoles can't reach the process, e.g. in containers or behind NAT.
+//! The message stream is sent via the `ConsoleCollector` service.
+//! Lost connections are re-established with exponential backoff,
+//! messages emitted in the meantime are lost. Failed attempts are reported
+//! with the next `SubscriberStats`.
+use crate::aggregator::{AggregatorSender, Message, Output};
+use crate::messages;
+
+use futures::future::{self, Loop};
+use futures::sync::mpsc;
+use futures::{stream, Async, Future, Stream};
+
+use hyper::client::connect::{Destination, HttpConnector};
+
+use tokio::timer::Delay;
+
+use tower_grpc::Request;
+use tower_hyper::{client, util};
+use tower_util::MakeService;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Delay before the first reconnect, doubled on each failed attempt
+const BACKOFF_MIN: Duration = Duration::from_millis(500);
+const BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+struct PushError {
+    /// Whether the stream was established, before the connection failed
+    connected: bool,
+    reason: String,
+}
+
+impl PushError {
+    fn connect<E: std::fmt::Debug>(e: E) -> PushError {
+        PushError {
+            connected: false,
+            reason: format!("{:?}", e),
+        }
+    }
+}
+
+/// Streams all messages to `addr`, resolves once all subscribers are dropped
+pub(crate) fn push(
+    messages: AggregatorSender<Message>,
+    outputs: AggregatorSender<Output>,
+    addr: &str,
+) -> impl Future<Item = (), Error = ()> {
+    let uri: http::Uri = addr.parse().expect("Invalid address");
+    future::loop_fn(BACKOFF_MIN, move |backoff| {
+        let messages = messages.clone();
+        push_once(&outputs, uri.clone()).then(move |result| {
+            let backoff = match result {
+                // The aggregator is gone
+                Ok(()) => return future::Either::A(future::ok(Loop::Break(()))),
+                Err(ref e) if e.connected => BACKOFF_MIN,
+                Err(_) => backoff,
+            };
+            if let Err(e) = result {
+                messages.try_send(Message::Error(format!(
+                    "push failed: {}, retrying in {:?}",
+                    e.reason, backoff
+                )));
+            }
+            let next = (backoff * 2).min(BACKOFF_MAX);
+            future::Either::B(
+                Delay::new(Instant::now() + backoff)
+                    .then(move |_| Ok::<_, ()>(Loop::Continue(next))),
+            )
+        })
+    })
+}
+
+/// A single connection, resolves once the aggregator is gone
+fn push_once(
+    outputs: &AggregatorSender<Output>,
+    uri: http::Uri,
+) -> impl Future<Item = (), Error = PushError> {
+    let outputs = outputs.clone();
+    let connector = util::Connector::new(HttpConnector::new(1));
+    let settings = client::Builder::new().http2_only(true).clone();
+    let mut make_client = client::Connect::with_builder(connector, settings);
+
+    future::result(Destination::try_from_uri(uri.clone()))
+        .map_err(PushError::connect)
+        .and_then(move |dst| make_client.make_service(dst).map_err(PushError::connect))
+        .and_then(move |conn| {
+            let conn = tower_request_modifier::Builder::new()
+                .set_origin(uri)
+                .build(conn)
+                .unwrap();
+            messages::client::ConsoleCollector::new(conn)
+                .ready()
+                .map_err(PushError::connect)
+        })
+        .and_then(move |mut client| {
+            // Only attach once connected, a blocking aggregator would stall otherwise
+            let (tx, rx) = mpsc::channel(8);
+            outputs.send(Output::Network(tx, None));
+            // Distinguishes the end of the stream from a collector which hung up
+            let ended = Arc::new(AtomicBool::new(false));
+            let end = ended.clone();
+            let stream = rx
+                .chain(stream::poll_fn(move || {
+                    end.store(true, Ordering::SeqCst);
+                    Ok(Async::Ready(None))
+                }))
+                .map_err(|()| {
+                    tower_grpc::Status::new(tower_grpc::Code::Internal, "aggregator failed")
+                });
+            client
+                .push(Request::new(stream))
+                .then(move |result| match result {
+                    Ok(_) if ended.load(Ordering::SeqCst) => Ok(()),
+                    Ok(_) => Err("closed by collector".to_string()),
+                    Err(e) => Err(format!("{:?}", e)),
+                })
+                .map_err(|reason| PushError {
+                    connected: true,
+                    reason,
+                })
+        })
+}
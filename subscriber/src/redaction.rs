@@ -0,0 +1,109 @@
+//! Redaction of sensitive field values, before they are sent to the aggregator
+//!
+//! See `BackgroundThreadHandle::redact`.
+use regex::Regex;
+
+use std::collections::hash_map::RandomState;
+use std::fmt::{self, Debug, Write};
+use std::hash::{BuildHasher, Hasher};
+
+/// Which fields a `Redaction` applies to
+#[derive(Clone, Debug)]
+pub enum FieldMatch {
+    /// Fields with exactly this name
+    Name(String),
+    /// Fields whose name matches the regex
+    Regex(Regex),
+}
+
+/// What redacted values are replaced with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Replacement {
+    /// `<redacted>`
+    Placeholder,
+    /// `<redacted:HASH>`, equal values have equal hashes within the process
+    ///
+    /// Allows to correlate values, without revealing them.
+    /// The hash is keyed per process, but values with little entropy,
+    /// like PINs, could still be guessed by the process itself.
+    Hash,
+}
+
+/// Replaces the values of matching span and event fields
+///
+/// ```rust,ignore
+/// use console_subscriber::{FieldMatch, Redaction, Replacement};
+///
+/// handle.redact(Redaction {
+///     field: FieldMatch::Regex(Regex::new("(?i)token|password").unwrap()),
+///     target: Some("my_app::auth".to_string()),
+///     replacement: Replacement::Hash,
+/// });
+/// ```
+#[derive(Clone, Debug)]
+pub struct Redaction {
+    pub field: FieldMatch,
+    /// Restricts the rule to this target and its submodules, e.g. `my_app::auth`
+    pub target: Option<String>,
+    pub replacement: Replacement,
+}
+
+impl Redaction {
+    fn matches(&self, target: &str, field: &str) -> bool {
+        let in_target = match &self.target {
+            Some(prefix) => {
+                target.starts_with(prefix.as_str())
+                    && (target.len() == prefix.len() || target[prefix.len()..].starts_with("::"))
+            }
+            None => true,
+        };
+        in_target
+            && match &self.field {
+                FieldMatch::Name(name) => name == field,
+                FieldMatch::Regex(regex) => regex.is_match(field),
+            }
+    }
+}
+
+/// All rules of a handle, see `Formatting`
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Redactions {
+    rules: Vec<Redaction>,
+    /// Random key of `Replacement::Hash`, fixed for the lifetime of the handle
+    hash_key: RandomState,
+}
+
+impl Redactions {
+    pub(crate) fn push(&mut self, rule: Redaction) {
+        self.rules.push(rule);
+    }
+
+    /// The replacement of the first rule matching `field` in `target`
+    pub(crate) fn find(&self, target: &str, field: &str) -> Option<Replacement> {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(target, field))
+            .map(|rule| rule.replacement)
+    }
+
+    /// The formatted value is hashed on the fly, it's never stored
+    pub(crate) fn replace(&self, replacement: Replacement, value: &dyn Debug) -> String {
+        match replacement {
+            Replacement::Placeholder => "<redacted>".to_string(),
+            Replacement::Hash => {
+                let mut writer = HashWriter(self.hash_key.build_hasher());
+                let _ = write!(writer, "{:?}", value);
+                format!("<redacted:{:016x}>", writer.0.finish())
+            }
+        }
+    }
+}
+
+struct HashWriter<H>(H);
+
+impl<H: Hasher> Write for HashWriter<H> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write(s.as_bytes());
+        Ok(())
+    }
+}
@@ -1,13 +1,21 @@
-use std::sync::{atomic::Ordering, Arc, RwLock};
+use std::io;
+use std::panic;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
+use std::time::Duration;
 
-use crossbeam::channel::{unbounded, Receiver, Sender};
+use crossbeam::channel::{self, unbounded, Receiver, Sender};
 
-use crate::messages::listen_response::Variant;
+use crate::aggregator::{self, AggregatorSender, AggregatorTask, Message, Output};
+use crate::discovery::Discovery;
+use crate::file::{FileSink, Rotation};
+use crate::loopback::Loopback;
+use crate::push;
 use crate::subscriber::*;
 use crate::*;
 
-use futures::sink::{Sink, Wait};
 use futures::sync::mpsc;
 use futures::Future;
 use futures::Stream;
@@ -18,22 +26,43 @@ use tower_grpc::codegen::server::grpc::{Request, Response};
 
 use tokio::net::TcpListener;
 
+/// Upper bound for the panic hook to wait on the aggregator
+const PANIC_FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
 #[derive(Default)]
 pub(crate) struct Registry {
     pub spans: Vec<Span>,
     pub reusable: Vec<SpanId>,
 
     pub thread_names: HashMap<ThreadId, String>,
+    /// Exits on another thread, the enters are still on the stack of the entering thread
+    pub remote_exits: Mutex<Vec<RemoteExit>>,
+}
+
+/// An exit without an enter on the exiting thread, see `ConsoleForwarder::exit`
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RemoteExit {
+    pub span: SpanId,
+    pub exited_at: i64,
+    /// The span was closed since, its busy time was reported already
+    pub closed: bool,
 }
 
 impl Registry {
-    pub(crate) fn new_id(&mut self) -> SpanId {
+    /// Children keep their parent alive, it's released once the child is dropped
+    pub(crate) fn new_id(&mut self, parent: Option<SpanId>, target: &'static str) -> SpanId {
+        if let Some(parent) = parent {
+            self.spans[parent.as_index()]
+                .refcount
+                .fetch_add(1, Ordering::SeqCst);
+        }
         self.reusable
             .pop()
             .map(|id| {
-                self.spans[id.as_index()]
-                    .refcount
-                    .fetch_add(1, Ordering::SeqCst);
+                let span = &mut self.spans[id.as_index()];
+                span.refcount.fetch_add(1, Ordering::SeqCst);
+                span.parent = parent;
+                span.target = target;
                 id
             })
             .unwrap_or_else(|| {
@@ -41,61 +70,99 @@ impl Registry {
                 self.spans.push(Span {
                     refcount: AtomicUsize::new(1),
                     follows: vec![],
+                    parent,
+                    target,
+                    first_entered: AtomicI64::new(0),
+                    busy_nanos: AtomicU64::new(0),
                 });
                 id
             })
     }
+
+    /// `parent`, followed by its ancestors up to the root
+    pub(crate) fn ancestors(&self, parent: Option<SpanId>) -> Vec<SpanId> {
+        let mut ancestors = vec![];
+        let mut next = parent;
+        while let Some(id) = next {
+            ancestors.push(id);
+            next = self.spans[id.as_index()].parent;
+        }
+        ancestors
+    }
 }
 
+/// Channels into the aggregator and the span registry
+///
+/// Shared by `BackgroundThreadHandle` and `BackgroundTaskHandle`.
 #[derive(Clone)]
-/// A factory for ConsoleForwarder
-pub struct BackgroundThreadHandle {
-    sender: Sender<Variant>,
-    tx_sender: Sender<Wait<mpsc::Sender<messages::ListenResponse>>>,
+pub(crate) struct Endpoint {
+    sender: AggregatorSender<Message>,
+    tx_sender: AggregatorSender<Output>,
     registry: Arc<RwLock<Registry>>,
+    formatting: Arc<messages::Formatting>,
+    ancestors: Arc<AtomicBool>,
 }
 
-impl BackgroundThreadHandle {
-    pub fn new() -> BackgroundThreadHandle {
-        let (tx, rx): (Sender<Variant>, Receiver<Variant>) = unbounded();
-        let (txtx, rxrx) = unbounded();
-        thread::spawn(move || {
-            let mut senders: Vec<Wait<mpsc::Sender<messages::ListenResponse>>> = Vec::new();
-            while let Ok(message) = rx.recv() {
-                while let Ok(tx) = rxrx.try_recv() {
-                    // TODO: Track and rebroadcast newspan information for live spans
-                    senders.push(tx);
-                }
-                let mut closed = vec![];
-                for (i, sender) in senders.iter_mut().enumerate() {
-                    let response = messages::ListenResponse {
-                        variant: Some(message.clone()),
-                    };
-                    if sender.send(response).is_err() {
-                        // Connection reset, mark for removal
-                        closed.push(i);
-                    }
-                }
-                // Traverse in reverse order, to keep index valid during removal
-                for &i in closed.iter().rev() {
-                    let _ = senders.remove(i);
-                }
-            }
-        });
-        BackgroundThreadHandle {
-            sender: tx,
-            tx_sender: txtx,
-            registry: Arc::default(),
+impl Endpoint {
+    fn new_subscriber(&self) -> ConsoleForwarder {
+        ConsoleForwarder {
+            tx: self.sender.clone(),
+            registry: self.registry.clone(),
+            formatting: self.formatting.clone(),
+            ancestors: self.ancestors.clone(),
         }
     }
 
-    pub fn into_server(self, addr: &str) -> impl Future<Item = (), Error = ()> {
+    fn record_to_file(&self, path: PathBuf, rotation: Rotation) -> io::Result<()> {
+        let sink = FileSink::create(path, rotation)?;
+        self.tx_sender.send(Output::File(sink));
+        Ok(())
+    }
+
+    /// Attaches a console, like `listen` does for remote ones
+    fn loopback(&self) -> Loopback {
+        let (tx, rx) = mpsc::channel(8);
+        self.tx_sender.send(Output::Network(tx, None));
+        Loopback::new(rx)
+    }
+
+    fn relay(&self, source: &str, response: &[u8]) -> Result<(), prost::DecodeError> {
+        let mut response: messages::ListenResponse = prost::Message::decode(response)?;
+        // Keep the original source, if relays are chained
+        if response.source.is_empty() {
+            response.source = source.to_string();
+        }
+        self.sender.send(Message::Relayed(response));
+        Ok(())
+    }
+
+    fn replay_buffer(&self, capacity: usize) {
+        self.sender.send(Message::ReplayBuffer(capacity));
+    }
+
+    fn into_push_client(self, addr: &str) -> impl Future<Item = (), Error = ()> {
+        push::push(self.sender, self.tx_sender, addr)
+    }
+
+    fn into_server(self, addr: &str) -> impl Future<Item = (), Error = ()> {
+        let bind = TcpListener::bind(&addr.parse().expect("Invalid address")).expect("bind");
+        self.serve(bind)
+    }
+
+    /// Binds an ephemeral port and announces it, see `discovery`
+    fn into_discoverable_server(
+        self,
+    ) -> io::Result<(impl Future<Item = (), Error = ()>, Discovery)> {
+        let bind = TcpListener::bind(&"[::1]:0".parse().unwrap())?;
+        let discovery = Discovery::register(bind.local_addr()?)?;
+        Ok((self.serve(bind), discovery))
+    }
+
+    fn serve(self, bind: TcpListener) -> impl Future<Item = (), Error = ()> {
         let service = messages::server::ConsoleForwarderServer::new(self);
         let mut server = Server::new(service);
         let http = Http::new().http2_only(true).clone();
 
-        let bind = TcpListener::bind(&addr.parse().expect("Invalid address")).expect("bind");
-
         bind.incoming()
             .for_each(move |sock| {
                 if let Err(e) = sock.set_nodelay(true) {
@@ -112,30 +179,320 @@ impl BackgroundThreadHandle {
             .map_err(|e| eprintln!("accept error: {}", e))
     }
 
-    pub fn run_background(self, addr: &'static str) -> thread::JoinHandle<()> {
-        thread::spawn(move || tokio::run(self.into_server(addr)))
+    fn sample_process(&self, interval: Option<Duration>) {
+        self.sender.send(Message::SampleProcess(interval));
     }
 
-    pub fn new_subscriber(&self) -> ConsoleForwarder {
-        ConsoleForwarder {
-            tx: self.sender.clone(),
-            registry: self.registry.clone(),
-        }
+    fn install_panic_hook(&self) {
+        let forwarder = self.new_subscriber();
+        let sender = self.sender.clone();
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            forwarder.record_panic(info);
+            // Make sure the panic reached all outputs, before unwinding continues.
+            // An aggregator task might have to run on the panicking thread, it isn't awaited.
+            if let AggregatorSender::Thread(_) = sender {
+                let (ack, done) = channel::bounded(1);
+                if sender.try_send(Message::Flush(ack)) {
+                    let _ = done.recv_timeout(PANIC_FLUSH_TIMEOUT);
+                }
+            }
+            previous(info);
+        }));
     }
 }
 
-impl messages::server::ConsoleForwarder for BackgroundThreadHandle {
+impl messages::server::ConsoleForwarder for Endpoint {
     type ListenStream =
         Box<dyn Stream<Item = messages::ListenResponse, Error = tower_grpc::Status> + Send>;
     type ListenFuture =
         futures::future::FutureResult<Response<Self::ListenStream>, tower_grpc::Status>;
 
-    fn listen(&mut self, _request: Request<messages::ListenRequest>) -> Self::ListenFuture {
+    fn listen(&mut self, request: Request<messages::ListenRequest>) -> Self::ListenFuture {
         let (tx, rx) = mpsc::channel(8);
-        self.tx_sender
-            .send(tx.wait())
-            .expect("BUG: No aggregation thread available");
+        let pretty = if request.get_ref().pretty_debug {
+            Some(messages::PrettyDebug::new(self.formatting.clone()))
+        } else {
+            None
+        };
+        self.tx_sender.send(Output::Network(tx, pretty));
         let rx = rx.map_err(|_| unimplemented!(""));
         futures::future::ok(Response::new(Box::new(rx)))
     }
 }
+
+#[derive(Clone)]
+/// A factory for ConsoleForwarder
+///
+/// Spawns an aggregator thread, the server runs on its own thread as well,
+/// see `BackgroundThreadHandle::run_background`.
+pub struct BackgroundThreadHandle {
+    endpoint: Endpoint,
+}
+
+impl BackgroundThreadHandle {
+    pub fn new() -> BackgroundThreadHandle {
+        let (tx, rx): (Sender<Message>, Receiver<Message>) = unbounded();
+        let (txtx, rxrx) = unbounded();
+        let registry: Arc<RwLock<Registry>> = Arc::default();
+        aggregator::spawn_thread(rx, rxrx, Arc::clone(&registry));
+        BackgroundThreadHandle {
+            endpoint: Endpoint {
+                sender: AggregatorSender::Thread(tx),
+                tx_sender: AggregatorSender::Thread(txtx),
+                registry,
+                formatting: Arc::default(),
+                ancestors: Arc::default(),
+            },
+        }
+    }
+
+    /// Records all subsequent messages into `path`, see `Rotation`
+    ///
+    /// Works instead of, or in addition to serving consoles.
+    /// The resulting file can be opened with `console --file <path>`.
+    pub fn record_to_file<P: Into<PathBuf>>(&self, path: P, rotation: Rotation) -> io::Result<()> {
+        self.endpoint.record_to_file(path.into(), rotation)
+    }
+
+    /// Attaches an in-process console, which receives all subsequent messages
+    ///
+    /// Messages take the same path as for consoles connected via gRPC.
+    /// The `Loopback` has to be drained, e.g. by `console::loopback::attach`,
+    /// otherwise the aggregator stalls.
+    pub fn loopback(&self) -> Loopback {
+        self.endpoint.loopback()
+    }
+
+    pub fn into_server(self, addr: &str) -> impl Future<Item = (), Error = ()> {
+        self.endpoint.into_server(addr)
+    }
+
+    /// Connects to the console or collector at `addr`, e.g. `http://collector:50052`
+    ///
+    /// Instead of waiting for consoles to connect, the message stream is pushed,
+    /// see `console --accept`. Reconnects with backoff, if the connection is lost.
+    pub fn into_push_client(self, addr: &str) -> impl Future<Item = (), Error = ()> {
+        self.endpoint.into_push_client(addr)
+    }
+
+    /// Like `run_background`, but pushes to `addr`, see `into_push_client`
+    pub fn run_push(self, addr: &'static str) -> thread::JoinHandle<()> {
+        thread::spawn(move || tokio::run(self.into_push_client(addr)))
+    }
+
+    /// Forwards an encoded `ListenResponse` of another process, e.g. received by a relay
+    ///
+    /// The message is tagged with `source`, unless it was tagged by another relay already.
+    /// From then on, the `SubscriberStats` of this process are left out.
+    pub fn relay(&self, source: &str, response: &[u8]) -> Result<(), prost::DecodeError> {
+        self.endpoint.relay(source, response)
+    }
+
+    /// Keeps the last `capacity` messages, which are sent to consoles once they connect
+    ///
+    /// Without it, consoles only see what happens after they connected.
+    /// `0` disables the buffer.
+    pub fn replay_buffer(&self, capacity: usize) {
+        self.endpoint.replay_buffer(capacity)
+    }
+
+    /// Installs a panic hook, which forwards panics as `Panic` messages
+    ///
+    /// The message contains payload, location, the span stack of the panicking thread
+    /// and a backtrace. It's flushed to all outputs, before the previously
+    /// installed hook is called and unwinding continues. Consoles receive it
+    /// from the server thread, if the process exits right away they might not.
+    pub fn install_panic_hook(&self) {
+        self.endpoint.install_panic_hook()
+    }
+
+    /// Samples CPU usage, RSS, open file descriptors and threads every `interval`
+    ///
+    /// Samples are emitted as `ProcessSample` messages, `None` stops sampling.
+    /// Only supported on Linux.
+    pub fn sample_process(&self, interval: Option<Duration>) {
+        self.endpoint.sample_process(interval)
+    }
+
+    pub fn run_background(self, addr: &'static str) -> thread::JoinHandle<()> {
+        thread::spawn(move || tokio::run(self.into_server(addr)))
+    }
+
+    /// Like `run_background`, but binds an ephemeral port
+    ///
+    /// The address is announced in `$XDG_RUNTIME_DIR/tracing-console/`,
+    /// where the console picks it up, without knowing the port upfront.
+    /// The announcement is removed, once the returned `Discovery` is dropped.
+    ///
+    /// ```rust,ignore
+    /// let (_server, _discovery) = handle.run_discoverable()?;
+    /// // Run the application, the announcement is removed when `main` returns
+    /// ```
+    pub fn run_discoverable(self) -> io::Result<(thread::JoinHandle<()>, Discovery)> {
+        let (server, discovery) = self.endpoint.into_discoverable_server()?;
+        Ok((thread::spawn(move || tokio::run(server)), discovery))
+    }
+
+    pub fn new_subscriber(&self) -> ConsoleForwarder {
+        self.endpoint.new_subscriber()
+    }
+
+    /// A `log::Log` implementation, forwarding records up to `level`
+    #[cfg(feature = "log")]
+    pub fn new_logger(&self, level: log::LevelFilter) -> ConsoleLogger {
+        ConsoleLogger::new(self.endpoint.new_subscriber(), level)
+    }
+
+    /// Limits formatted `Debug` values to `max_len` bytes, `None` disables the limit
+    ///
+    /// Truncated values end with a "truncated, N bytes omitted" marker.
+    pub fn truncate_debug(&self, max_len: Option<usize>) {
+        self.endpoint.formatting.set_max_len(max_len)
+    }
+
+    /// Replaces values of fields matching `rule`, before they leave the recording thread
+    ///
+    /// Applies to all subsequent spans, records, events and `log` records.
+    /// The first matching rule wins.
+    pub fn redact(&self, rule: Redaction) {
+        self.endpoint.formatting.redact(rule)
+    }
+
+    /// Sends the whole ancestor chain of the parent span with each event
+    ///
+    /// Allows consoles to filter and group by any enclosing span,
+    /// not only the innermost one.
+    pub fn send_ancestors(&self, enabled: bool) {
+        self.endpoint.ancestors.store(enabled, Ordering::Relaxed)
+    }
+}
+
+#[derive(Clone)]
+/// A factory for ConsoleForwarder, without additional threads
+///
+/// The aggregator and the server run as tasks on the applications tokio runtime.
+/// Consoles which can't keep up miss messages, instead of blocking the runtime.
+///
+/// ```rust,ignore
+/// use console_subscriber::BackgroundTaskHandle;
+///
+/// tokio::run(futures::future::lazy(|| {
+///     let handle = BackgroundTaskHandle::new();
+///     let subscriber = handle.new_subscriber();
+///     // Install subscriber, spawn application tasks...
+///     handle.into_server("[::1]:50051")
+/// }));
+/// ```
+pub struct BackgroundTaskHandle {
+    endpoint: Endpoint,
+}
+
+impl BackgroundTaskHandle {
+    /// Spawns the aggregator task
+    ///
+    /// # Panics
+    /// When called outside of a tokio runtime
+    pub fn new() -> BackgroundTaskHandle {
+        let (tx, rx) = mpsc::unbounded();
+        let (txtx, rxrx) = mpsc::unbounded();
+        let queue_depth: Arc<AtomicUsize> = Arc::default();
+        let registry: Arc<RwLock<Registry>> = Arc::default();
+        tokio::spawn(AggregatorTask::new(
+            rx,
+            Arc::clone(&queue_depth),
+            rxrx,
+            Arc::clone(&registry),
+        ));
+        BackgroundTaskHandle {
+            endpoint: Endpoint {
+                sender: AggregatorSender::Task(tx, queue_depth),
+                tx_sender: AggregatorSender::Task(txtx, Arc::default()),
+                registry,
+                formatting: Arc::default(),
+                ancestors: Arc::default(),
+            },
+        }
+    }
+
+    /// See `BackgroundThreadHandle::record_to_file`
+    ///
+    /// Writes happen on the aggregator task and block the executor briefly.
+    pub fn record_to_file<P: Into<PathBuf>>(&self, path: P, rotation: Rotation) -> io::Result<()> {
+        self.endpoint.record_to_file(path.into(), rotation)
+    }
+
+    /// Attaches an in-process console, see `BackgroundThreadHandle::loopback`
+    ///
+    /// Messages are dropped, if the `Loopback` isn't drained fast enough.
+    pub fn loopback(&self) -> Loopback {
+        self.endpoint.loopback()
+    }
+
+    /// Accepts consoles on `addr`, spawn the returned future on the runtime
+    pub fn into_server(self, addr: &str) -> impl Future<Item = (), Error = ()> {
+        self.endpoint.into_server(addr)
+    }
+
+    /// See `BackgroundThreadHandle::run_discoverable`
+    ///
+    /// Keep the `Discovery` until the runtime shuts down, it removes the announcement on drop.
+    pub fn into_discoverable_server(
+        self,
+    ) -> io::Result<(impl Future<Item = (), Error = ()>, Discovery)> {
+        self.endpoint.into_discoverable_server()
+    }
+
+    /// See `BackgroundThreadHandle::into_push_client`
+    pub fn into_push_client(self, addr: &str) -> impl Future<Item = (), Error = ()> {
+        self.endpoint.into_push_client(addr)
+    }
+
+    /// See `BackgroundThreadHandle::relay`
+    pub fn relay(&self, source: &str, response: &[u8]) -> Result<(), prost::DecodeError> {
+        self.endpoint.relay(source, response)
+    }
+
+    /// See `BackgroundThreadHandle::replay_buffer`
+    pub fn replay_buffer(&self, capacity: usize) {
+        self.endpoint.replay_buffer(capacity)
+    }
+
+    /// See `BackgroundThreadHandle::install_panic_hook`
+    ///
+    /// The panicking thread doesn't wait for the flush, the aggregator task might
+    /// share its runtime.
+    pub fn install_panic_hook(&self) {
+        self.endpoint.install_panic_hook()
+    }
+
+    /// See `BackgroundThreadHandle::sample_process`
+    pub fn sample_process(&self, interval: Option<Duration>) {
+        self.endpoint.sample_process(interval)
+    }
+
+    pub fn new_subscriber(&self) -> ConsoleForwarder {
+        self.endpoint.new_subscriber()
+    }
+
+    /// A `log::Log` implementation, forwarding records up to `level`
+    #[cfg(feature = "log")]
+    pub fn new_logger(&self, level: log::LevelFilter) -> ConsoleLogger {
+        ConsoleLogger::new(self.endpoint.new_subscriber(), level)
+    }
+
+    /// See `BackgroundThreadHandle::truncate_debug`
+    pub fn truncate_debug(&self, max_len: Option<usize>) {
+        self.endpoint.formatting.set_max_len(max_len)
+    }
+
+    /// See `BackgroundThreadHandle::redact`
+    pub fn redact(&self, rule: Redaction) {
+        self.endpoint.formatting.redact(rule)
+    }
+
+    /// See `BackgroundThreadHandle::send_ancestors`
+    pub fn send_ancestors(&self, enabled: bool) {
+        self.endpoint.ancestors.store(enabled, Ordering::Relaxed)
+    }
+}
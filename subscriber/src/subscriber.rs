@@ -3,26 +3,40 @@ use tracing_core::Event;
 use tracing_core::Subscriber;
 use tracing_core::{Interest, Metadata};
 
-use crossbeam::channel::Sender;
-
 use std::cell::{Cell, RefCell};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::panic::PanicInfo;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Once, RwLock};
 use std::thread;
 
+use backtrace::Backtrace;
+
 use chrono::prelude::*;
 
+use crate::aggregator::{AggregatorSender, Message};
 use crate::messages::listen_response::Variant;
 use crate::messages::Recorder;
 use crate::*;
 
 static THREAD_COUNTER: AtomicUsize = AtomicUsize::new(1);
+/// Number of pending `Registry::remote_exits`, allows to skip the purge in the common case
+static REMOTE_EXITS: AtomicUsize = AtomicUsize::new(0);
 
 thread_local! {
     static THREAD_ID_INIT: Once = Once::new();
     static THREAD_ID: Cell<usize> = Cell::new(0);
 
-    static STACK: RefCell<Vec<SpanId>> = RefCell::new(Vec::new());
+    /// Entered spans and the timestamp of the enter, innermost last
+    static STACK: RefCell<Vec<(SpanId, i64)>> = RefCell::new(Vec::new());
+}
+
+/// Adds the time between an enter and its exit to the busy time of `span`
+fn add_busy_time(span: &Span, entered_at: i64, exited_at: i64) {
+    let _ = span
+        .first_entered
+        .compare_exchange(0, entered_at, Ordering::SeqCst, Ordering::SeqCst);
+    span.busy_nanos
+        .fetch_add((exited_at - entered_at).max(0) as u64, Ordering::SeqCst);
 }
 
 fn get_thread_id(console: &ConsoleForwarder) -> ThreadId {
@@ -40,15 +54,146 @@ fn get_thread_id(console: &ConsoleForwarder) -> ThreadId {
     })
 }
 
+/// The id of this thread, if it was assigned one already
+///
+/// Unlike `get_thread_id`, it never takes the registry lock, so it can't block or panic.
+fn try_get_thread_id() -> Option<ThreadId> {
+    match THREAD_ID.try_with(Cell::get) {
+        Ok(0) | Err(_) => None,
+        Ok(id) => Some(ThreadId(id)),
+    }
+}
+
 pub struct ConsoleForwarder {
-    pub(crate) tx: Sender<Variant>,
+    pub(crate) tx: AggregatorSender<Message>,
     pub(crate) registry: Arc<RwLock<crate::Registry>>,
+    pub(crate) formatting: Arc<messages::Formatting>,
+    /// See `BackgroundThreadHandle::send_ancestors`
+    pub(crate) ancestors: Arc<AtomicBool>,
 }
 
 impl ConsoleForwarder {
     fn register_thread_name(&self, id: ThreadId, name: String) {
         self.registry.write().unwrap().thread_names.insert(id, name);
     }
+
+    pub(crate) fn send(&self, variant: Variant) {
+        self.tx.send(Message::Variant(variant));
+    }
+
+    pub(crate) fn thread_id(&self) -> ThreadId {
+        get_thread_id(self)
+    }
+
+    /// The innermost span entered on this thread
+    pub(crate) fn current_span(&self) -> Option<SpanId> {
+        if REMOTE_EXITS.load(Ordering::SeqCst) > 0 {
+            self.purge_remote_exits();
+        }
+        STACK.with(|stack| stack.borrow().last().map(|(id, _)| *id))
+    }
+
+    /// Removes spans from the stack of this thread, which were exited on another thread
+    ///
+    /// Only this thread's stack is touched, the busy time of the enter is added on the way.
+    /// Exits don't tell which enter they belong to. If a span is entered on several threads
+    /// at once, whichever of them purges first claims the exit, not necessarily the thread
+    /// the exit belonged to. This is a known limitation.
+    fn purge_remote_exits(&self) {
+        let registry = try_lock!(self.registry.read());
+        let mut remote_exits = try_lock!(registry.remote_exits.lock());
+        STACK.with(|stack| {
+            let mut stack = stack.borrow_mut();
+            // Innermost first, like exits on this thread
+            for i in (0..stack.len()).rev() {
+                let (id, entered_at) = stack[i];
+                let claimed = remote_exits
+                    .iter()
+                    .position(|exit| exit.span == id && exit.exited_at >= entered_at);
+                let exit = match claimed {
+                    Some(claimed) => remote_exits.swap_remove(claimed),
+                    None => continue,
+                };
+                REMOTE_EXITS.fetch_sub(1, Ordering::SeqCst);
+                stack.remove(i);
+                let reentered = stack[..i].iter().any(|(entered, _)| *entered == id);
+                if !exit.closed && !reentered {
+                    add_busy_time(&registry.spans[id.as_index()], entered_at, exit.exited_at);
+                }
+            }
+        });
+    }
+
+    /// The explicit parent, the current span for contextual spans/events or none for roots
+    pub(crate) fn resolve_parent(
+        &self,
+        explicit: Option<&span::Id>,
+        is_contextual: bool,
+    ) -> Option<SpanId> {
+        match explicit {
+            Some(parent) => Some(SpanId::new(parent.into_u64())),
+            None if is_contextual => self.current_span(),
+            None => None,
+        }
+    }
+
+    /// `parent` and its ancestors, if enabled
+    pub(crate) fn ancestors(&self, parent: Option<SpanId>) -> Vec<messages::SpanId> {
+        if !self.ancestors.load(Ordering::Relaxed) {
+            return vec![];
+        }
+        self.registry
+            .read()
+            .unwrap()
+            .ancestors(parent)
+            .iter()
+            .map(SpanId::as_message)
+            .collect()
+    }
+
+    /// Emits a `Panic` message, must be called on the panicking thread
+    pub(crate) fn record_panic(&self, info: &PanicInfo) {
+        let payload = info.payload();
+        let payload = if let Some(s) = payload.downcast_ref::<&str>() {
+            s.to_string()
+        } else if let Some(s) = payload.downcast_ref::<String>() {
+            s.clone()
+        } else {
+            "Box<Any>".to_string()
+        };
+        // The thread local might be gone already, or borrowed by the panicking code
+        let spans = STACK
+            .try_with(|stack| {
+                stack
+                    .try_borrow()
+                    .map(|stack| stack.iter().map(|(id, _)| id.as_message()).collect())
+                    .unwrap_or_default()
+            })
+            .unwrap_or_default();
+
+        // Panicking again would abort the process
+        let _ = self
+            .tx
+            .try_send(Message::Variant(Variant::Panic(messages::Panic {
+                payload,
+                file: info
+                    .location()
+                    .map(|l| l.file().to_string())
+                    .unwrap_or_default(),
+                line: info.location().map(|l| messages::LineNum { num: l.line() }),
+                column: info
+                    .location()
+                    .map(|l| messages::LineNum { num: l.column() }),
+                // Registering the thread takes the registry lock, which might be poisoned
+                thread: try_get_thread_id().map(Into::into),
+                thread_name: thread::current().name().unwrap_or("<unnamed>").to_string(),
+                spans,
+                backtrace: format!("{:?}", Backtrace::new()),
+                timestamp: Some(messages::Timestamp {
+                    nano: Utc::now().timestamp_nanos(),
+                }),
+            })));
+    }
 }
 
 impl Subscriber for ConsoleForwarder {
@@ -56,46 +201,45 @@ impl Subscriber for ConsoleForwarder {
         true
     }
     fn new_span(&self, span: &span::Attributes) -> span::Id {
-        let id = self.registry.write().unwrap().new_id();
-        let mut rec = Recorder::default();
+        let parent = self.resolve_parent(span.parent(), span.is_contextual());
+        let target = span.metadata().target();
+        let id = self.registry.write().unwrap().new_id(parent, target);
+        let mut rec = Recorder::new(&self.formatting, target);
         span.record(&mut rec);
-        self.tx
-            .send(Variant::NewSpan(messages::NewSpan {
-                attributes: Some(span.into()),
-                span: Some(id.as_message()),
-                timestamp: Some(messages::Timestamp {
-                    nano: Utc::now().timestamp_nanos(),
-                }),
-                values: rec.0,
-            }))
-            .expect("BUG: No Backgroundthread");
+        self.send(Variant::NewSpan(messages::NewSpan {
+            attributes: Some(span.into()),
+            span: Some(id.as_message()),
+            timestamp: Some(messages::Timestamp {
+                nano: Utc::now().timestamp_nanos(),
+            }),
+            values: rec.values,
+            parent: parent.as_ref().map(SpanId::as_message),
+        }));
 
         id.as_span()
     }
     fn record(&self, span: &span::Id, values: &span::Record) {
-        let mut recorder = messages::Recorder::default();
+        let target =
+            try_lock!(self.registry.read()).spans[SpanId::new(span.into_u64()).as_index()].target;
+        let mut recorder = Recorder::new(&self.formatting, target);
         values.record(&mut recorder);
-        self.tx
-            .send(Variant::Record(messages::Record {
-                span: Some(span.into()),
-                values: recorder.0,
-                thread: Some(get_thread_id(self).into()),
-                timestamp: Some(messages::Timestamp {
-                    nano: Utc::now().timestamp_nanos(),
-                }),
-            }))
-            .expect("BUG: No Backgroundthread");
+        self.send(Variant::Record(messages::Record {
+            span: Some(span.into()),
+            values: recorder.values,
+            thread: Some(get_thread_id(self).into()),
+            timestamp: Some(messages::Timestamp {
+                nano: Utc::now().timestamp_nanos(),
+            }),
+        }));
     }
     fn record_follows_from(&self, span: &span::Id, follows: &span::Id) {
-        self.tx
-            .send(Variant::Follows(messages::RecordFollowsFrom {
-                span: Some(span.into()),
-                follows: Some(follows.into()),
-            }))
-            .expect("BUG: No Backgroundthread");
+        self.send(Variant::Follows(messages::RecordFollowsFrom {
+            span: Some(span.into()),
+            follows: Some(follows.into()),
+        }));
     }
     fn event(&self, event: &Event) {
-        let mut recorder = messages::Recorder::default();
+        let mut recorder = Recorder::new(&self.formatting, event.metadata().target());
         event.record(&mut recorder);
         let fields = event
             .fields()
@@ -109,24 +253,56 @@ impl Subscriber for ConsoleForwarder {
             metadata: Some(event.metadata().into()),
             parent: event.parent().map(|p| p.into()),
         };
-        self.tx
-            .send(Variant::Event(messages::Event {
-                span: STACK.with(|stack| stack.borrow().last().map(SpanId::as_message)),
-                values: recorder.0,
-                thread: Some(get_thread_id(self).into()),
-                attributes: Some(attributes),
-                fields,
-                timestamp: Some(messages::Timestamp {
-                    nano: Utc::now().timestamp_nanos(),
-                }),
-            }))
-            .expect("BUG: No Backgroundthread");
+        let parent = self.resolve_parent(event.parent(), event.is_contextual());
+        self.send(Variant::Event(messages::Event {
+            span: parent.as_ref().map(SpanId::as_message),
+            ancestors: self.ancestors(parent),
+            values: recorder.values,
+            thread: Some(get_thread_id(self).into()),
+            attributes: Some(attributes),
+            fields,
+            timestamp: Some(messages::Timestamp {
+                nano: Utc::now().timestamp_nanos(),
+            }),
+        }));
     }
     fn enter(&self, span: &span::Id) {
-        STACK.with(|stack| stack.borrow_mut().push(SpanId::new(span.into_u64())))
+        let entered = (SpanId::new(span.into_u64()), Utc::now().timestamp_nanos());
+        STACK.with(|stack| stack.borrow_mut().push(entered))
     }
-    fn exit(&self, _: &span::Id) {
-        STACK.with(|stack| stack.borrow_mut().pop());
+    /// Spans are not necessarily exited in the order they were entered,
+    /// or on the same thread, e.g. when a future moves between worker threads.
+    ///
+    /// The time in between is added to the busy time of the span,
+    /// unless it's still entered further out, which then accounts for it.
+    /// An exit on another thread is left to the entering thread, see `purge_remote_exits`.
+    fn exit(&self, span: &span::Id) {
+        let id = SpanId::new(span.into_u64());
+        let exited_at = Utc::now().timestamp_nanos();
+        let found = STACK.with(|stack| {
+            let mut stack = stack.borrow_mut();
+            let i = stack.iter().rposition(|(entered, _)| *entered == id)?;
+            let (_, entered_at) = stack.remove(i);
+            let reentered = stack[..i].iter().any(|(entered, _)| *entered == id);
+            Some((entered_at, reentered))
+        });
+        match found {
+            Some((entered_at, false)) => {
+                let registry = try_lock!(self.registry.read());
+                add_busy_time(&registry.spans[id.as_index()], entered_at, exited_at);
+            }
+            Some((_, true)) => {}
+            None => {
+                // The entering thread cleans up lazily
+                let registry = try_lock!(self.registry.read());
+                try_lock!(registry.remote_exits.lock()).push(RemoteExit {
+                    span: id,
+                    exited_at,
+                    closed: false,
+                });
+                REMOTE_EXITS.fetch_add(1, Ordering::SeqCst);
+            }
+        }
     }
     fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
         match self.enabled(metadata) {
@@ -147,10 +323,296 @@ impl Subscriber for ConsoleForwarder {
             .refcount
             .fetch_sub(1, Ordering::SeqCst);
         if old_count == 1 {
-            let mut registry = try_lock!(self.registry.write());
-            registry.spans[index].follows.clear();
+            let (parent, first_entered, busy_nanos) = {
+                let mut registry = try_lock!(self.registry.write());
+                // Pending exits still purge the stacks, but don't add to the next use
+                for exit in try_lock!(registry.remote_exits.get_mut()).iter_mut() {
+                    if exit.span.as_index() == index {
+                        exit.closed = true;
+                    }
+                }
+                let span = &mut registry.spans[index];
+                span.follows.clear();
+                let first_entered = span.first_entered.swap(0, Ordering::SeqCst);
+                let busy_nanos = span.busy_nanos.swap(0, Ordering::SeqCst);
+                let parent = span.parent.take();
+
+                registry.reusable.push(SpanId::new(id.into_u64()));
+                (parent, first_entered, busy_nanos)
+            };
+            self.send(Variant::Close(messages::SpanClose {
+                span: Some(SpanId::new(id.into_u64()).as_message()),
+                timestamp: Some(messages::Timestamp {
+                    nano: Utc::now().timestamp_nanos(),
+                }),
+                first_entered: match first_entered {
+                    0 => None,
+                    nano => Some(messages::Timestamp { nano }),
+                },
+                busy_nanos,
+            }));
+            // Release the reference held on the parent, see `Registry::new_id`
+            if let Some(parent) = parent {
+                self.drop_span(parent.as_span());
+            }
+        }
+    }
+}
 
-            registry.reusable.push(SpanId::new(id.into_u64()));
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crossbeam::channel::{self, Receiver};
+
+    use tracing::{info, span, Level};
+    use tracing_core::dispatcher::{self, Dispatch};
+
+    use std::time::Duration;
+
+    fn dispatch() -> (Dispatch, Receiver<Message>) {
+        let (tx, rx) = channel::unbounded();
+        let forwarder = ConsoleForwarder {
+            tx: AggregatorSender::Thread(tx),
+            registry: Arc::default(),
+            formatting: Arc::default(),
+            ancestors: Arc::default(),
+        };
+        (Dispatch::new(forwarder), rx)
+    }
+
+    fn enter(id: &span::Id) {
+        dispatcher::get_default(|dispatch| dispatch.enter(id))
+    }
+
+    fn exit(id: &span::Id) {
+        dispatcher::get_default(|dispatch| dispatch.exit(id))
+    }
+
+    /// Message and parent of all events received so far
+    fn events(rx: &Receiver<Message>) -> Vec<(String, Option<u64>)> {
+        rx.try_iter()
+            .filter_map(|message| match message {
+                Message::Variant(Variant::Event(event)) => {
+                    let message = event.values.iter().find_map(|value| match &value.value {
+                        Some(messages::value::Value::Debug(debug)) => Some(debug.debug.clone()),
+                        _ => None,
+                    });
+                    Some((message?, event.span.map(|span| span.id)))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// A thread which runs jobs with a default dispatcher, like a runtime worker
+    struct Worker {
+        jobs: channel::Sender<Box<dyn FnOnce() + Send>>,
+        done: Receiver<()>,
+    }
+
+    impl Worker {
+        fn new(dispatch: &Dispatch) -> Worker {
+            let dispatch = dispatch.clone();
+            let (jobs, rx) = channel::unbounded::<Box<dyn FnOnce() + Send>>();
+            let (done_tx, done) = channel::unbounded();
+            thread::spawn(move || {
+                dispatcher::with_default(&dispatch, || {
+                    for job in rx {
+                        job();
+                        done_tx.send(()).unwrap();
+                    }
+                })
+            });
+            Worker { jobs, done }
         }
+
+        /// Runs `job` on the worker thread and waits for it to finish
+        fn run<F: FnOnce() + Send + 'static>(&self, job: F) {
+            self.jobs.send(Box::new(job)).unwrap();
+            self.done.recv().unwrap();
+        }
+    }
+
+    /// Two spans, which outlive the test body, and their ids
+    fn spans(dispatch: &Dispatch) -> ([tracing::Span; 2], span::Id, span::Id) {
+        let spans = dispatcher::with_default(dispatch, || {
+            [span!(Level::INFO, "a"), span!(Level::INFO, "b")]
+        });
+        let a = spans[0].id().expect("span is disabled");
+        let b = spans[1].id().expect("span is disabled");
+        (spans, a, b)
+    }
+
+    #[test]
+    fn close_reports_busy_time() {
+        let (dispatch, rx) = dispatch();
+        let (spans, a, _) = spans(&dispatch);
+        dispatcher::with_default(&dispatch, || {
+            enter(&a);
+            enter(&a);
+            thread::sleep(Duration::from_millis(5));
+            exit(&a);
+            exit(&a);
+        });
+        drop(spans);
+
+        let closes: Vec<messages::SpanClose> = rx
+            .try_iter()
+            .filter_map(|message| match message {
+                Message::Variant(Variant::Close(close)) => Some(close),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(closes.len(), 2);
+        let close = closes
+            .iter()
+            .find(|close| close.span.as_ref().unwrap().id == a.into_u64())
+            .unwrap();
+        let first_entered = close.first_entered.as_ref().unwrap().nano;
+        // The re-entrant enter isn't counted twice
+        assert!(close.busy_nanos >= 5_000_000);
+        assert!(close.busy_nanos as i64 <= close.timestamp.as_ref().unwrap().nano - first_entered);
+    }
+
+    #[test]
+    fn exit_out_of_order() {
+        let (dispatch, rx) = dispatch();
+        let (_spans, a, b) = spans(&dispatch);
+        dispatcher::with_default(&dispatch, || {
+            enter(&a);
+            enter(&b);
+            exit(&a);
+            info!("in b");
+            exit(&b);
+            info!("root");
+        });
+        assert_eq!(
+            events(&rx),
+            vec![
+                ("in b".to_string(), Some(b.into_u64())),
+                ("root".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn reentrant_span() {
+        let (dispatch, rx) = dispatch();
+        let (_spans, a, b) = spans(&dispatch);
+        dispatcher::with_default(&dispatch, || {
+            enter(&a);
+            enter(&b);
+            enter(&a);
+            info!("inner a");
+            exit(&a);
+            info!("b");
+            exit(&b);
+            info!("outer a");
+            exit(&a);
+        });
+        assert_eq!(
+            events(&rx),
+            vec![
+                ("inner a".to_string(), Some(a.into_u64())),
+                ("b".to_string(), Some(b.into_u64())),
+                ("outer a".to_string(), Some(a.into_u64())),
+            ]
+        );
+    }
+
+    #[test]
+    fn work_stealing() {
+        let (dispatch, rx) = dispatch();
+        let workers = [Worker::new(&dispatch), Worker::new(&dispatch)];
+        let (_spans, task, other) = spans(&dispatch);
+
+        // Each poll enters and exits the span, but on whichever worker is free
+        let poll = |worker: &Worker, id: &span::Id, message: &'static str| {
+            let id = id.clone();
+            worker.run(move || {
+                enter(&id);
+                info!("{}", message);
+                exit(&id);
+            });
+        };
+        poll(&workers[0], &task, "task on 0");
+        poll(&workers[1], &task, "task stolen by 1");
+        poll(&workers[0], &other, "other on 0");
+        poll(&workers[1], &other, "other stolen by 1");
+        workers[0].run(|| info!("idle 0"));
+        workers[1].run(|| info!("idle 1"));
+
+        assert_eq!(
+            events(&rx),
+            vec![
+                ("task on 0".to_string(), Some(task.into_u64())),
+                ("task stolen by 1".to_string(), Some(task.into_u64())),
+                ("other on 0".to_string(), Some(other.into_u64())),
+                ("other stolen by 1".to_string(), Some(other.into_u64())),
+                ("idle 0".to_string(), None),
+                ("idle 1".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn cross_thread_exit() {
+        let (dispatch, rx) = dispatch();
+        let workers = [Worker::new(&dispatch), Worker::new(&dispatch)];
+        let (_spans, task, other) = spans(&dispatch);
+
+        let id = task.clone();
+        workers[0].run(move || {
+            enter(&id);
+            info!("entered on 0");
+        });
+        let (id, other_id) = (task.clone(), other.clone());
+        workers[1].run(move || {
+            enter(&other_id);
+            // The guard moved along with the future
+            exit(&id);
+            info!("other on 1");
+        });
+        workers[0].run(|| info!("after exit on 0"));
+
+        assert_eq!(
+            events(&rx),
+            vec![
+                ("entered on 0".to_string(), Some(task.into_u64())),
+                ("other on 1".to_string(), Some(other.into_u64())),
+                ("after exit on 0".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn cross_thread_exit_reports_busy_time() {
+        let (dispatch, rx) = dispatch();
+        let workers = [Worker::new(&dispatch), Worker::new(&dispatch)];
+        let (spans, task, _) = spans(&dispatch);
+
+        let id = task.clone();
+        workers[0].run(move || enter(&id));
+        thread::sleep(Duration::from_millis(5));
+        let id = task.clone();
+        workers[1].run(move || exit(&id));
+        // Counted once the entering thread looks at its stack again
+        workers[0].run(|| info!("after exit on 0"));
+        drop(spans);
+
+        let close = rx
+            .try_iter()
+            .find_map(|message| match message {
+                Message::Variant(Variant::Close(ref close))
+                    if close.span.as_ref().unwrap().id == task.into_u64() =>
+                {
+                    Some(close.clone())
+                }
+                _ => None,
+            })
+            .unwrap();
+        assert!(close.first_entered.is_some());
+        assert!(close.busy_nanos >= 5_000_000);
     }
 }